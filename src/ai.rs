@@ -3,45 +3,54 @@ use crate::component::*;
 use crate::asset::*;
 use crate::NUM_ITEM;
 
-use std::f32::consts::E;
+use std::f32::consts::{ E, PI };
+use std::collections::HashMap;
 
 use amethyst::{
     core::{
+        math::{ Point3, Vector3 },
         Transform,
     },
     ecs::{
-        Entity, Entities, Read, WriteStorage, Join,
-    },
-    tiles::{
-        TileMap, Map,
+        Entity, Entities, Read, ReadExpect, ReadStorage, WriteStorage,
     },
 };
 
 use serde::{ Serialize, Deserialize };
+use rand::prelude::*;
+use rand::distributions::WeightedIndex;
 
-use std::collections::HashMap;
 
 pub type AIData<'a> = (
     &'a Entities<'a>,
     Read<'a, Vec<AssetWorkplaceData>>,
     Read<'a, Vec<AssetItemData>>,
     Read<'a, Vec<AIAxis>>,
-    WriteStorage<'a, TileMap<MiscTile>>,
+    ReadExpect<'a, MiscChunks>,
     WriteStorage<'a, Transform>,
     WriteStorage<'a, ComponentWorkplace>,
     WriteStorage<'a, ComponentStockpile>,
     WriteStorage<'a, ComponentMovement>,
     WriteStorage<'a, ComponentPrice>,
+    Read<'a, MiscSpatialGrid>,
+    Read<'a, MiscTravel>,
+    ReadStorage<'a, ComponentMemory>,
 );
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum AICurveType {
     Quadratic,
     Logistic,
     Logit,
+    Exponential,
+    Sine,
+    Step,
+    Normal,
+    Inverse,
+    Piecewise(Vec<(f32, f32)>),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum AIInputType {
     MyStockpileOre,
     MyStockpileIngot,
@@ -59,9 +68,11 @@ pub enum AIInputType {
     CanSellOre,
     CanSellIngot,
     CanSellTools,
+    CrowdAtTarget,
+    TravelTime,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AIAxis {
     pub name: String,
     pub curve: AICurveType,
@@ -71,125 +82,354 @@ pub struct AIAxis {
     pub k: f32,
     pub b: f32,
     pub c: f32,
+    #[serde(default)]
+    pub invert: bool,
+    #[serde(default)]
+    pub range: Option<(f32, f32)>,
+}
+
+pub const AXIS_PARAMS: [(&str, f32); 5] = [("m", 0.5), ("k", 0.05), ("b", 0.05), ("c", 0.05), ("foo", 1.0)];
+
+impl AIAxis {
+    pub fn get_param(&self, param: usize) -> f32 {
+        match param {
+            0 => return self.m,
+            1 => return self.k,
+            2 => return self.b,
+            3 => return self.c,
+            _ => return self.foo,
+        }
+    }
+
+    pub fn get_param_mut(&mut self, param: usize) -> &mut f32 {
+        match param {
+            0 => return &mut self.m,
+            1 => return &mut self.k,
+            2 => return &mut self.b,
+            3 => return &mut self.c,
+            _ => return &mut self.foo,
+        }
+    }
+
+    pub fn get_bounds(&self, param: usize) -> (f32, f32) {
+        match (param, &self.curve) {
+            (0, _) => return (-20.0, 20.0),
+            (1, AICurveType::Quadratic) => return (0.0, 10.0),
+            (1, AICurveType::Logit) | (1, AICurveType::Normal) => return (0.05, 10.0),
+            (1, _) => return (-10.0, 10.0),
+            (2, _) => return (-1.0, 1.0),
+            (3, _) => return (0.0, 1.0),
+            _ => return (0.05, 1000.0),
+        }
+    }
+
+    pub fn set_param(&mut self, param: usize, value: f32) -> bool {
+        let (min, max) = self.get_bounds(param);
+        let old = self.get_param(param);
+
+        *self.get_param_mut(param) = value.max(min).min(max);
+
+        if !check_curve(self).is_empty() {
+            *self.get_param_mut(param) = old;
+            return false;
+        }
+
+        return true;
+    }
+
+    pub fn get_output(&self, x: f32) -> f32 {
+        let mut y = response_curve(&self.curve, x, self.m, self.k, self.b, self.c);
+
+        if self.invert {
+            y = 1.0 - y;
+        }
+        if let Some((min, max)) = self.range {
+            y = clamp(y.max(min).min(max));
+        }
+
+        return y;
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AIAxisScore {
+    pub axis: u16,
+    pub input: f32,
+    pub output: f32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AIScore {
+    pub action: u8,
+    pub target: Option<u32>,
+    pub axes: Vec<AIAxisScore>,
+    pub weight: f32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AIDecision {
+    pub time: String,
+    pub scores: Vec<AIScore>,
+    pub action: u8,
+    pub target: Option<u32>,
+    pub weight: f32,
+}
+
+pub const MEMORY_SIGHT: f32 = 3.0;
+pub const MEMORY_GOSSIP: f32 = 2.0;
+pub const MEMORY_GOSSIP_TICKS: u32 = 30;
+
+#[derive(Clone, Copy)]
+pub struct AIMemory {
+    pub buy: [u16; NUM_ITEM],
+    pub sell: [u16; NUM_ITEM],
+    pub stock: [u16; NUM_ITEM],
+    pub time: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AIActionData {
+    pub name: String,
+    pub axis: Vec<u16>,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    #[serde(default)]
+    pub cutoff: f32,
+    #[serde(default = "default_compensate")]
+    pub compensate: bool,
+    #[serde(default)]
+    pub priority: u8,
+    #[serde(default)]
+    pub effect: Option<AIEffect>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum AIEffect {
+    Work(u8),
+    Buy(usize),
+    Sell(usize),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AIGoal {
+    Money,
+    Stock(usize, u16),
+}
+
+pub const ACTION_IDLE: u8 = 0;
+pub const SELECTION_DEFAULT: AISelection = AISelection::Weighted(5.0);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AISelection {
+    Argmax,
+    Weighted(f32),
+    TopN(usize),
+    Softmax(f32),
+    Bucketed,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AIArchetypeData {
+    pub name: String,
+    pub selection: AISelection,
+    #[serde(default)]
+    pub reevaluate: u32,
+    #[serde(default = "default_weight")]
+    pub momentum: f32,
+    #[serde(default)]
+    pub goal: Option<AIGoal>,
+    #[serde(default = "default_depth")]
+    pub depth: usize,
+}
+
+fn default_depth() -> usize {
+    return 4;
+}
+
+pub struct AIPlanner<'a> {
+    pub goal: &'a AIGoal,
+    pub steps: Vec<(u8, AIEffect)>,
+    pub workplace_datas: &'a [AssetWorkplaceData],
+    pub buy: [u16; NUM_ITEM],
+    pub sell: [u16; NUM_ITEM],
+}
+impl<'a> AIPlanner<'a> {
+    pub fn plan(&self, items: [u16; NUM_ITEM], depth: usize) -> Vec<u8> {
+        let mut best = (self.get_value(&items), Vec::new());
+
+        self.search(items, depth, &mut Vec::new(), &mut best);
+
+        return best.1;
+    }
+
+    fn search(&self, items: [u16; NUM_ITEM], depth: usize, plan: &mut Vec<u8>, best: &mut (f32, Vec<u8>)) {
+        let value = self.get_value(&items);
+
+        if value > best.0 || (value == best.0 && plan.len() < best.1.len()) {
+            *best = (value, plan.clone());
+        }
+
+        if depth == 0 {
+            return;
+        }
+
+        for (action, effect) in self.steps.iter() {
+            if let Some(next) = self.get_effect(effect, &items) {
+                plan.push(*action);
+                self.search(next, depth - 1, plan, best);
+                plan.pop();
+            }
+        }
+    }
+
+    pub fn get_effect(&self, effect: &AIEffect, items: &[u16; NUM_ITEM]) -> Option<[u16; NUM_ITEM]> {
+        let mut out = *items;
+
+        match effect {
+            AIEffect::Work(variant) => {
+                let workplace_data = self.workplace_datas.get(*variant as usize)?;
+
+                for (i, input) in workplace_data.inputs.iter().enumerate().take(NUM_ITEM) {
+                    out[i] = out[i].checked_sub(*input as u16)?;
+                }
+                for (i, output) in workplace_data.outs.iter().enumerate().take(NUM_ITEM) {
+                    out[i] = out[i].saturating_add(*output as u16);
+                }
+            }
+            AIEffect::Buy(item) => {
+                let price = *self.buy.get(*item)?;
+
+                if price == 0 {
+                    return None;
+                }
+
+                out[0] = out[0].checked_sub(price)?;
+                out[*item] = out[*item].saturating_add(1);
+            }
+            AIEffect::Sell(item) => {
+                let price = *self.sell.get(*item)?;
+
+                if price == 0 {
+                    return None;
+                }
+
+                out[*item] = out[*item].checked_sub(1)?;
+                out[0] = out[0].saturating_add(price);
+            }
+        }
+
+        return Some(out);
+    }
+
+    pub fn get_value(&self, items: &[u16; NUM_ITEM]) -> f32 {
+        match self.goal {
+            AIGoal::Money => return items[0] as f32,
+            AIGoal::Stock(item, count) => return items.get(*item).map_or(0, |stock| (*stock).min(*count)) as f32,
+        }
+    }
+}
+
+fn default_weight() -> f32 {
+    return 1.0;
+}
+
+fn default_compensate() -> bool {
+    return true;
 }
 
 pub trait AIAction: Send + Sync {
     fn get_name(&self) -> &String;
-    fn get_delay(&self) -> &HashMap<Entity, u32>;
+    fn get_data(&self) -> &AIActionData;
 
-    fn eval(&self, _: &Entity, _: &AIData) -> Option<(u8, Option<Entity>, f32)>;
+    fn eval(&self, _: &Entity, _: &AIData, _: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)>;
 
-    fn init(&mut self, _: &Entity, _: &Entity, _: &mut AIData) -> bool;
-    fn run(&mut self, _: &Entity, _: &Entity, _: &mut AIData) -> bool;
+    fn init(&self, _: &Entity, _: &Entity, _: &mut ComponentActionState, _: &AIData, _: &mut Vec<AICommand>) -> bool;
+    fn run(&self, _: &Entity, _: &Entity, _: &mut ComponentActionState, _: &AIData, _: &mut Vec<AICommand>) -> bool;
+    fn abort(&self, _: &Entity, _: &mut ComponentActionState, _: &AIData, _: &mut Vec<AICommand>);
 }
 
 pub struct AIActionIdle {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 pub struct AIActionWorkAtSmithy {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 pub struct AIActionWorkAtFurnace {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 pub struct AIActionWorkAtMine {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 pub struct AIActionBuyOre {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 pub struct AIActionBuyIngot {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 pub struct AIActionBuyTools {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 pub struct AIActionSellOre {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 pub struct AIActionSellIngot {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 pub struct AIActionSellTools {
-    pub name: String,
-    pub axis: Vec<u16>,
-    pub delays: HashMap<Entity, u32>,
+    pub data: AIActionData,
 }
 
 
 impl AIAction for AIActionIdle {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, _: &Entity, _: &AIData) -> Option<(u8, Option<Entity>, f32)> {
+    fn eval(&self, _: &Entity, _: &AIData, trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        if let Some(trace) = trace {
+            trace.push(AIScore { action: 0, target: None, axes: Vec::new(), weight: 0.0 });
+        }
+
         return Some((0, None, 0.0));
     }
 
-    fn init(&mut self, _: &Entity, _: &Entity, _: &mut AIData) -> bool {
+    fn init(&self, _: &Entity, _: &Entity, _: &mut ComponentActionState, _: &AIData, _: &mut Vec<AICommand>) -> bool {
         return true;
     }
-    fn run(&mut self, _: &Entity, _: &Entity, _: &mut AIData) -> bool {
+    fn run(&self, _: &Entity, _: &Entity, _: &mut ComponentActionState, _: &AIData, _: &mut Vec<AICommand>) -> bool {
         return true;
     }
+    fn abort(&self, _: &Entity, _: &mut ComponentActionState, _: &AIData, _: &mut Vec<AICommand>) {
+    }
 }
 
 impl AIAction for AIActionWorkAtSmithy {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, me: &Entity, ai_data: &AIData) -> Option<(u8, Option<Entity>, f32)> {
-        let (entities, _, _, axis_datas, _, _, workplaces, _, _, _) = &ai_data;
+    fn eval(&self, me: &Entity, ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        let (_, _, _, _, _, transforms, workplaces, _, _, _, spatial, _, _) = &ai_data;
 
         let mut out = (3, None, 0.0);
 
-        for (target, workplace) in (*entities, workplaces).join() {
-            if workplace.variant == 2 {
-                let mut weight = 1.0;
+        let center = transforms.get(*me).unwrap().translation();
 
-                for axis_index in self.axis.iter() {
-                    let axis = &axis_datas[*axis_index as usize];
+        for target in spatial.get_nearest(center, spatial.candidates, |e| workplaces.get(e).map_or(false, |w| w.variant == 2)) {
+            let weight = get_score(3, &self.data, me, &target, ai_data, trace.as_mut().map(|trace| &mut **trace));
 
-                    let x = clearing_house(&axis.input, me, &target, axis.foo, &ai_data);
-                    let y = response_curve(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
-
-                    weight *= y;
-                }
-
-                if weight > out.2 {
-                    out.1 = Some(target);
-                    out.2 = weight;
-                }
+            if weight > out.2 {
+                out.1 = Some(target);
+                out.2 = weight;
             }
         }
 
-        //println!("{}: {}", self.name, out.2);
-
         if out.2 > 0.0 {
             return Some(out);
         } else {
@@ -197,105 +437,65 @@ impl AIAction for AIActionWorkAtSmithy {
         }
     }
 
-    fn init(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, tilemaps, transforms, _, _, movements, _) = ai_data;
-        let tilemap = (tilemaps).join().next().unwrap();
-
-        let me_point = tilemap.to_tile(transforms.get(*me).unwrap().translation(), None).unwrap();
-        let target_point = tilemap.to_tile(transforms.get(*target).unwrap().translation(), None).unwrap();
-
-        if me_point != target_point {
-            let targets = get_targets(&me_point, &target_point, &tilemap);
-
-            if targets.len() > 0 {
-                movements.get_mut(*me).unwrap().targets = targets;
-            } else {
-                return false;
-            }
-        } else {
-            movements.get_mut(*me).unwrap().targets.clear();
-        }
-
-        return true;
+    fn init(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        return init_move_to(me, target, ai_data, commands);
     }
-    fn run(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, workplace_datas, _, _, _, _, workplaces, stockpiles, movements, prices) = ai_data;
+    fn run(&self, me: &Entity, target: &Entity, state: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        let (_, workplace_datas, _, _, _, _, workplaces, _, movements, _, _, _, _) = ai_data;
 
         if movements.get(*me).unwrap().targets.len() > 0 {
             return false;
         }
 
-        if let Some(delay) = self.delays.get_mut(me) {
+        if let Some(delay) = state.delay.as_mut() {
             if *delay > 0 {
                 *delay -= 1;
 
                 return false;
             }
 
-            self.delays.remove(me);
-
-            let workplace = workplaces.get(*target).unwrap();
-            let stockpile = stockpiles.get_mut(*me).unwrap();
-
-            for (i, out) in workplace_datas[workplace.variant as usize].outs.iter().enumerate() {
-                if *out > 0 {
-                    stockpile.items[i] += *out as u16;
-                }
-            }
-            for (i, input) in workplace_datas[workplace.variant as usize].inputs.iter().enumerate() {
-                if *input > 0 {
-                    stockpile.items[i] -= *input as u16;
-                }
-            }
+            state.delay = None;
 
-            prices.get_mut(*me).unwrap().update[1] = true;
+            commands.push(AICommand::Produce(*me, workplaces.get(*target).unwrap().variant, 1));
 
             return true;
         } else {
             let workplace = workplaces.get(*target).unwrap();
 
-            self.delays.insert(*me, workplace_datas[workplace.variant as usize].duration);
+            state.delay = Some(workplace_datas[workplace.variant as usize].duration);
 
             return false;
         }
     }
+    fn abort(&self, me: &Entity, state: &mut ComponentActionState, _: &AIData, commands: &mut Vec<AICommand>) {
+        abort_move(me, state, commands);
+    }
 }
 
 impl AIAction for AIActionWorkAtFurnace {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, me: &Entity, ai_data: &AIData) -> Option<(u8, Option<Entity>, f32)> {
-        let (entities, _, _, axis_datas, _, _, workplaces, _, _, _) = &ai_data;
+    fn eval(&self, me: &Entity, ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        let (_, _, _, _, _, transforms, workplaces, _, _, _, spatial, _, _) = &ai_data;
 
         let mut out = (2, None, 0.0);
 
-        for (target, workplace) in (*entities, workplaces).join() {
-            if workplace.variant == 1 {
-                let mut weight = 1.0;
-
-                for axis_index in self.axis.iter() {
-                    let axis = &axis_datas[*axis_index as usize];
-
-                    let x = clearing_house(&axis.input, me, &target, axis.foo, &ai_data);
-                    let y = response_curve(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
+        let center = transforms.get(*me).unwrap().translation();
 
-                    weight *= y;
-                }
+        for target in spatial.get_nearest(center, spatial.candidates, |e| workplaces.get(e).map_or(false, |w| w.variant == 1)) {
+            let weight = get_score(2, &self.data, me, &target, ai_data, trace.as_mut().map(|trace| &mut **trace));
 
-                if weight > out.2 {
-                    out.1 = Some(target);
-                    out.2 = weight;
-                }
+            if weight > out.2 {
+                out.1 = Some(target);
+                out.2 = weight;
             }
         }
 
-        //println!("{}: {}", self.name, out.2);
-
         if out.2 > 0.0 {
             return Some(out);
         } else {
@@ -303,105 +503,65 @@ impl AIAction for AIActionWorkAtFurnace {
         }
     }
 
-    fn init(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, tilemaps, transforms, _, _, movements, _) = ai_data;
-        let tilemap = (tilemaps).join().next().unwrap();
-
-        let me_point = tilemap.to_tile(transforms.get(*me).unwrap().translation(), None).unwrap();
-        let target_point = tilemap.to_tile(transforms.get(*target).unwrap().translation(), None).unwrap();
-
-        if me_point != target_point {
-            let targets = get_targets(&me_point, &target_point, &tilemap);
-
-            if targets.len() > 0 {
-                movements.get_mut(*me).unwrap().targets = targets;
-            } else {
-                return false;
-            }
-        } else {
-            movements.get_mut(*me).unwrap().targets.clear();
-        }
-
-        return true;
+    fn init(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        return init_move_to(me, target, ai_data, commands);
     }
-    fn run(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, workplace_datas, _, _, _, _, workplaces, stockpiles, movements, prices) = ai_data;
+    fn run(&self, me: &Entity, target: &Entity, state: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        let (_, workplace_datas, _, _, _, _, workplaces, _, movements, _, _, _, _) = ai_data;
 
         if movements.get(*me).unwrap().targets.len() > 0 {
             return false;
         }
 
-        if let Some(delay) = self.delays.get_mut(me) {
+        if let Some(delay) = state.delay.as_mut() {
             if *delay > 0 {
                 *delay -= 1;
 
                 return false;
             }
 
-            self.delays.remove(me);
-
-            let workplace = workplaces.get(*target).unwrap();
-            let stockpile = stockpiles.get_mut(*me).unwrap();
-
-            for (i, out) in workplace_datas[workplace.variant as usize].outs.iter().enumerate() {
-                if *out > 0 {
-                    stockpile.items[i] += *out as u16;
-                }
-            }
-            for (i, input) in workplace_datas[workplace.variant as usize].inputs.iter().enumerate() {
-                if *input > 0 {
-                    stockpile.items[i] -= *input as u16;
-                }
-            }
+            state.delay = None;
 
-            prices.get_mut(*me).unwrap().update[2] = true;
+            commands.push(AICommand::Produce(*me, workplaces.get(*target).unwrap().variant, 2));
 
             return true;
         } else {
             let workplace = workplaces.get(*target).unwrap();
 
-            self.delays.insert(*me, workplace_datas[workplace.variant as usize].duration);
+            state.delay = Some(workplace_datas[workplace.variant as usize].duration);
 
             return false;
         }
     }
+    fn abort(&self, me: &Entity, state: &mut ComponentActionState, _: &AIData, commands: &mut Vec<AICommand>) {
+        abort_move(me, state, commands);
+    }
 }
 
 impl AIAction for AIActionWorkAtMine {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, me: &Entity, ai_data: &AIData) -> Option<(u8, Option<Entity>, f32)> {
-        let (entities, _, _, axis_datas, _, _, workplaces, _, _, _) = ai_data;
+    fn eval(&self, me: &Entity, ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        let (_, _, _, _, _, transforms, workplaces, _, _, _, spatial, _, _) = ai_data;
 
         let mut out = (1, None, 0.0);
 
-        for (target, workplace) in (*entities, workplaces).join() {
-            if workplace.variant == 0 {
-                let mut weight = 1.0;
+        let center = transforms.get(*me).unwrap().translation();
 
-                for axis_index in self.axis.iter() {
-                    let axis = &axis_datas[*axis_index as usize];
+        for target in spatial.get_nearest(center, spatial.candidates, |e| workplaces.get(e).map_or(false, |w| w.variant == 0)) {
+            let weight = get_score(1, &self.data, me, &target, ai_data, trace.as_mut().map(|trace| &mut **trace));
 
-                    let x = clearing_house(&axis.input, me, &target, axis.foo, &ai_data);
-                    let y = response_curve(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
-
-                    weight *= y;
-                }
-
-                if weight > out.2 {
-                    out.1 = Some(target);
-                    out.2 = weight;
-                }
+            if weight > out.2 {
+                out.1 = Some(target);
+                out.2 = weight;
             }
         }
 
-        //println!("{}: {}", self.name, out.2);
-
         if out.2 > 0.0 {
             return Some(out);
         } else {
@@ -409,105 +569,65 @@ impl AIAction for AIActionWorkAtMine {
         }
     }
 
-    fn init(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, tilemaps, transforms, _, _, movements, _) = ai_data;
-        let tilemap = (tilemaps).join().next().unwrap();
-
-        let me_point = tilemap.to_tile(transforms.get(*me).unwrap().translation(), None).unwrap();
-        let target_point = tilemap.to_tile(transforms.get(*target).unwrap().translation(), None).unwrap();
-
-        if me_point != target_point {
-            let targets = get_targets(&me_point, &target_point, &tilemap);
-
-            if targets.len() > 0 {
-                movements.get_mut(*me).unwrap().targets = targets;
-            } else {
-                return false;
-            }
-        } else {
-            movements.get_mut(*me).unwrap().targets.clear();
-        }
-
-        return true;
+    fn init(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        return init_move_to(me, target, ai_data, commands);
     }
-    fn run(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, workplace_datas, _, _, _, _, workplaces, stockpiles, movements, prices) = ai_data;
+    fn run(&self, me: &Entity, target: &Entity, state: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        let (_, workplace_datas, _, _, _, _, workplaces, _, movements, _, _, _, _) = ai_data;
 
         if movements.get(*me).unwrap().targets.len() > 0 {
             return false;
         }
 
-        if let Some(delay) = self.delays.get_mut(me) {
+        if let Some(delay) = state.delay.as_mut() {
             if *delay > 0 {
                 *delay -= 1;
 
                 return false;
             }
 
-            self.delays.remove(me);
-
-            let workplace = workplaces.get(*target).unwrap();
-            let stockpile = stockpiles.get_mut(*me).unwrap();
-
-            for (i, out) in workplace_datas[workplace.variant as usize].outs.iter().enumerate() {
-                if *out > 0 {
-                    stockpile.items[i] += *out as u16;
-                }
-            }
-            for (i, input) in workplace_datas[workplace.variant as usize].inputs.iter().enumerate() {
-                if *input > 0 {
-                    stockpile.items[i] -= *input as u16;
-                }
-            }
+            state.delay = None;
 
-            prices.get_mut(*me).unwrap().update[3] = true;
+            commands.push(AICommand::Produce(*me, workplaces.get(*target).unwrap().variant, 3));
 
             return true;
         } else {
             let workplace = workplaces.get(*target).unwrap();
 
-            self.delays.insert(*me, workplace_datas[workplace.variant as usize].duration);
+            state.delay = Some(workplace_datas[workplace.variant as usize].duration);
 
             return false;
         }
     }
+    fn abort(&self, me: &Entity, state: &mut ComponentActionState, _: &AIData, commands: &mut Vec<AICommand>) {
+        abort_move(me, state, commands);
+    }
 }
 
 impl AIAction for AIActionBuyOre {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, me: &Entity, ai_data: &AIData) -> Option<(u8, Option<Entity>, f32)> {
-        let (entities, _, _, axis_datas, _, _, workplaces, stockpiles, _, prices) = ai_data;
+    fn eval(&self, me: &Entity, ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        let (_, _, _, _, _, transforms, workplaces, _, _, _, spatial, _, _) = ai_data;
 
         let mut out = (4, None, 0.0);
 
-        for (target, workplace, _, stockpile) in (*entities, workplaces, prices, stockpiles).join() {
-            if workplace.variant == 3 && stockpile.items[1] >= 1 {
-                let mut weight = 1.0;
-
-                for axis_index in self.axis.iter() {
-                    let axis = &axis_datas[*axis_index as usize];
-
-                    let x = clearing_house(&axis.input, me, &target, axis.foo, &ai_data);
-                    let y = response_curve(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
+        let center = transforms.get(*me).unwrap().translation();
 
-                    weight *= y;
-                }
+        for target in spatial.get_nearest(center, spatial.candidates, |e| workplaces.get(e).map_or(false, |w| w.variant == 3) && get_quote(me, &e, ai_data).map_or(false, |quote| quote.stock[1] >= 1)) {
+            let weight = get_score(4, &self.data, me, &target, ai_data, trace.as_mut().map(|trace| &mut **trace));
 
-                if weight > out.2 {
-                    out.1 = Some(target);
-                    out.2 = weight;
-                }
+            if weight > out.2 {
+                out.1 = Some(target);
+                out.2 = weight;
             }
         }
 
-        //println!("{}: {}", self.name, out.2);
-
         if out.2 > 0.0 {
             return Some(out);
         } else {
@@ -515,89 +635,51 @@ impl AIAction for AIActionBuyOre {
         }
     }
 
-    fn init(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, tilemaps, transforms, _, _, movements, _) = ai_data;
-        let tilemap = (tilemaps).join().next().unwrap();
-
-        let me_point = tilemap.to_tile(transforms.get(*me).unwrap().translation(), None).unwrap();
-        let target_point = tilemap.to_tile(transforms.get(*target).unwrap().translation(), None).unwrap();
-
-        if me_point != target_point {
-            let targets = get_targets(&me_point, &target_point, &tilemap);
-
-            if targets.len() > 0 {
-                movements.get_mut(*me).unwrap().targets = targets;
-            } else {
-                return false;
-            }
-        } else {
-            movements.get_mut(*me).unwrap().targets.clear();
-        }
-
-        return true;
+    fn init(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        return init_move_to(me, target, ai_data, commands);
     }
-    fn run(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, _, _, _, stockpiles, movements, prices) = ai_data;
+    fn run(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        let (_, _, _, _, _, _, _, _, movements, prices, _, _, _) = ai_data;
 
         if movements.get(*me).unwrap().targets.len() > 0 {
             return false;
         }
         
-        let stockpile = stockpiles.get_mut(*target).unwrap();
-        let price = prices.get(*target).unwrap();
-
-        if prices.get(*me).unwrap().buy[1] > price.sell[1] && stockpile.items[1] >= 1 {
-            stockpile.items[1] -= 1;
-            stockpile.items[0] += price.sell[1];
-
-            let stockpile = stockpiles.get_mut(*me).unwrap();
-
-            stockpile.items[1] += 1;
-            stockpile.items[0] -= price.sell[1];
-
-            prices.get_mut(*me).unwrap().update = [true; NUM_ITEM];
-            prices.get_mut(*target).unwrap().update = [true; NUM_ITEM];
+        if prices.get(*me).unwrap().buy[1] > prices.get(*target).unwrap().sell[1] {
+            commands.push(AICommand::Buy(*me, *target, 1));
         }
 
         return true;
     }
+    fn abort(&self, me: &Entity, state: &mut ComponentActionState, _: &AIData, commands: &mut Vec<AICommand>) {
+        abort_move(me, state, commands);
+    }
 }
 
 impl AIAction for AIActionBuyIngot {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, me: &Entity, ai_data: &AIData) -> Option<(u8, Option<Entity>, f32)> {
-        let (entities, _, _, axis_datas, _, _, workplaces, stockpiles, _, prices) = ai_data;
+    fn eval(&self, me: &Entity, ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        let (_, _, _, _, _, transforms, workplaces, _, _, _, spatial, _, _) = ai_data;
 
         let mut out = (5, None, 0.0);
 
-        for (target, workplace, _, stockpile) in (*entities, workplaces, prices, stockpiles).join() {
-            if workplace.variant == 3 && stockpile.items[2] >= 1 {
-                let mut weight = 1.0;
-
-                for axis_index in self.axis.iter() {
-                    let axis = &axis_datas[*axis_index as usize];
+        let center = transforms.get(*me).unwrap().translation();
 
-                    let x = clearing_house(&axis.input, me, &target, axis.foo, &ai_data);
-                    let y = response_curve(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
-
-                    weight *= y;
-                }
+        for target in spatial.get_nearest(center, spatial.candidates, |e| workplaces.get(e).map_or(false, |w| w.variant == 3) && get_quote(me, &e, ai_data).map_or(false, |quote| quote.stock[2] >= 1)) {
+            let weight = get_score(5, &self.data, me, &target, ai_data, trace.as_mut().map(|trace| &mut **trace));
 
-                if weight > out.2 {
-                    out.1 = Some(target);
-                    out.2 = weight;
-                }
+            if weight > out.2 {
+                out.1 = Some(target);
+                out.2 = weight;
             }
         }
 
-        //println!("{}: {}", self.name, out.2);
-
         if out.2 > 0.0 {
             return Some(out);
         } else {
@@ -605,89 +687,51 @@ impl AIAction for AIActionBuyIngot {
         }
     }
 
-    fn init(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, tilemaps, transforms, _, _, movements, _) = ai_data;
-        let tilemap = (tilemaps).join().next().unwrap();
-
-        let me_point = tilemap.to_tile(transforms.get(*me).unwrap().translation(), None).unwrap();
-        let target_point = tilemap.to_tile(transforms.get(*target).unwrap().translation(), None).unwrap();
-
-        if me_point != target_point {
-            let targets = get_targets(&me_point, &target_point, &tilemap);
-
-            if targets.len() > 0 {
-                movements.get_mut(*me).unwrap().targets = targets;
-            } else {
-                return false;
-            }
-        } else {
-            movements.get_mut(*me).unwrap().targets.clear();
-        }
-
-        return true;
+    fn init(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        return init_move_to(me, target, ai_data, commands);
     }
-    fn run(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, _, _, _, stockpiles, movements, prices) = ai_data;
+    fn run(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        let (_, _, _, _, _, _, _, _, movements, prices, _, _, _) = ai_data;
 
         if movements.get(*me).unwrap().targets.len() > 0 {
             return false;
         }
         
-        let stockpile = stockpiles.get_mut(*target).unwrap();
-        let price = prices.get(*target).unwrap();
-
-        if prices.get(*me).unwrap().buy[2] > price.sell[2] && stockpile.items[2] >= 1 {
-            stockpile.items[2] -= 1;
-            stockpile.items[0] += price.sell[2];
-
-            let stockpile = stockpiles.get_mut(*me).unwrap();
-
-            stockpile.items[2] += 1;
-            stockpile.items[0] -= price.sell[2];
-
-            prices.get_mut(*me).unwrap().update = [true; NUM_ITEM];
-            prices.get_mut(*target).unwrap().update = [true; NUM_ITEM];
+        if prices.get(*me).unwrap().buy[2] > prices.get(*target).unwrap().sell[2] {
+            commands.push(AICommand::Buy(*me, *target, 2));
         }
 
         return true;
     }
+    fn abort(&self, me: &Entity, state: &mut ComponentActionState, _: &AIData, commands: &mut Vec<AICommand>) {
+        abort_move(me, state, commands);
+    }
 }
 
 impl AIAction for AIActionBuyTools {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, me: &Entity, ai_data: &AIData) -> Option<(u8, Option<Entity>, f32)> {
-        let (entities, _, _, axis_datas, _, _, workplaces, stockpiles, _, prices) = ai_data;
+    fn eval(&self, me: &Entity, ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        let (_, _, _, _, _, transforms, workplaces, _, _, _, spatial, _, _) = ai_data;
 
         let mut out = (6, None, 0.0);
 
-        for (target, workplace, _, stockpile) in (*entities, workplaces, prices, stockpiles).join() {
-            if workplace.variant == 3 && stockpile.items[3] >= 1 {
-                let mut weight = 1.0;
+        let center = transforms.get(*me).unwrap().translation();
 
-                for axis_index in self.axis.iter() {
-                    let axis = &axis_datas[*axis_index as usize];
+        for target in spatial.get_nearest(center, spatial.candidates, |e| workplaces.get(e).map_or(false, |w| w.variant == 3) && get_quote(me, &e, ai_data).map_or(false, |quote| quote.stock[3] >= 1)) {
+            let weight = get_score(6, &self.data, me, &target, ai_data, trace.as_mut().map(|trace| &mut **trace));
 
-                    let x = clearing_house(&axis.input, me, &target, axis.foo, &ai_data);
-                    let y = response_curve(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
-
-                    weight *= y;
-                }
-
-                if weight > out.2 {
-                    out.1 = Some(target);
-                    out.2 = weight;
-                }
+            if weight > out.2 {
+                out.1 = Some(target);
+                out.2 = weight;
             }
         }
 
-        //println!("{}: {}", self.name, out.2);
-
         if out.2 > 0.0 {
             return Some(out);
         } else {
@@ -695,81 +739,47 @@ impl AIAction for AIActionBuyTools {
         }
     }
 
-    fn init(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, tilemaps, transforms, _, _, movements, _) = ai_data;
-        let tilemap = (tilemaps).join().next().unwrap();
-
-        let me_point = tilemap.to_tile(transforms.get(*me).unwrap().translation(), None).unwrap();
-        let target_point = tilemap.to_tile(transforms.get(*target).unwrap().translation(), None).unwrap();
-
-        if me_point != target_point {
-            let targets = get_targets(&me_point, &target_point, &tilemap);
-
-            if targets.len() > 0 {
-                movements.get_mut(*me).unwrap().targets = targets;
-            } else {
-                return false;
-            }
-        } else {
-            movements.get_mut(*me).unwrap().targets.clear();
-        }
-
-        return true;
-    }
-    fn run(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, _, _, _, stockpiles, movements, prices) = ai_data;
+    fn init(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        return init_move_to(me, target, ai_data, commands);
+    }
+    fn run(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        let (_, _, _, _, _, _, _, _, movements, prices, _, _, _) = ai_data;
 
         if movements.get(*me).unwrap().targets.len() > 0 {
             return false;
         }
         
-        let stockpile = stockpiles.get_mut(*target).unwrap();
-        let price = prices.get(*target).unwrap();
-
-        if prices.get(*me).unwrap().buy[3] > price.sell[3] && stockpile.items[3] >= 1 {
-            stockpile.items[3] -= 1;
-            stockpile.items[0] += price.sell[3];
-
-            let stockpile = stockpiles.get_mut(*me).unwrap();
-
-            stockpile.items[3] += 1;
-            stockpile.items[0] -= price.sell[3];
-
-            prices.get_mut(*me).unwrap().update = [true; NUM_ITEM];
-            prices.get_mut(*target).unwrap().update = [true; NUM_ITEM];
+        if prices.get(*me).unwrap().buy[3] > prices.get(*target).unwrap().sell[3] {
+            commands.push(AICommand::Buy(*me, *target, 3));
         }
 
         return true;
     }
+    fn abort(&self, me: &Entity, state: &mut ComponentActionState, _: &AIData, commands: &mut Vec<AICommand>) {
+        abort_move(me, state, commands);
+    }
 }
 
 impl AIAction for AIActionSellOre {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, me: &Entity, ai_data: &AIData) -> Option<(u8, Option<Entity>, f32)> {
-        let (entities, _, _, axis_datas, _, _, workplaces, stockpiles, _, prices) = ai_data;
+    fn eval(&self, me: &Entity, ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        let (_, _, _, _, _, transforms, workplaces, stockpiles, _, _, spatial, _, _) = ai_data;
 
         let mut out = (7, None, 0.0);
 
         let stockpile = stockpiles.get(*me).unwrap();
 
-        for (target, workplace, _, _) in (*entities, workplaces, prices, stockpiles).join() {
-            if workplace.variant == 3 && stockpile.items[1] >= 1 {
-                let mut weight = 1.0;
-
-                for axis_index in self.axis.iter() {
-                    let axis = &axis_datas[*axis_index as usize];
-
-                    let x = clearing_house(&axis.input, me, &target, axis.foo, &ai_data);
-                    let y = response_curve(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
+        let center = transforms.get(*me).unwrap().translation();
 
-                    weight *= y;
-                }
+        if stockpile.items[1] >= 1 {
+            for target in spatial.get_nearest(center, spatial.candidates, |e| workplaces.get(e).map_or(false, |w| w.variant == 3) && get_quote(me, &e, ai_data).is_some()) {
+                let weight = get_score(7, &self.data, me, &target, ai_data, trace.as_mut().map(|trace| &mut **trace));
 
                 if weight > out.2 {
                     out.1 = Some(target);
@@ -778,8 +788,6 @@ impl AIAction for AIActionSellOre {
             }
         }
 
-        //println!("{}: {}", self.name, out.2);
-
         if out.2 > 0.0 {
             return Some(out);
         } else {
@@ -787,81 +795,47 @@ impl AIAction for AIActionSellOre {
         }
     }
 
-    fn init(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, tilemaps, transforms, _, _, movements, _) = ai_data;
-        let tilemap = (tilemaps).join().next().unwrap();
-
-        let me_point = tilemap.to_tile(transforms.get(*me).unwrap().translation(), None).unwrap();
-        let target_point = tilemap.to_tile(transforms.get(*target).unwrap().translation(), None).unwrap();
-
-        if me_point != target_point {
-            let targets = get_targets(&me_point, &target_point, &tilemap);
-
-            if targets.len() > 0 {
-                movements.get_mut(*me).unwrap().targets = targets;
-            } else {
-                return false;
-            }
-        } else {
-            movements.get_mut(*me).unwrap().targets.clear();
-        }
-
-        return true;
+    fn init(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        return init_move_to(me, target, ai_data, commands);
     }
-    fn run(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, _, _, _, stockpiles, movements, prices) = ai_data;
+    fn run(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        let (_, _, _, _, _, _, _, _, movements, prices, _, _, _) = ai_data;
 
         if movements.get(*me).unwrap().targets.len() > 0 {
             return false;
         }
         
-        let stockpile = stockpiles.get_mut(*target).unwrap();
-        let price = prices.get(*target).unwrap();
-
-        if prices.get(*me).unwrap().sell[1] < price.buy[1] && stockpile.items[0] >= price.buy[1] {
-            stockpile.items[1] += 1;
-            stockpile.items[0] -= price.buy[1];
-
-            let stockpile = stockpiles.get_mut(*me).unwrap();
-
-            stockpile.items[1] -= 1;
-            stockpile.items[0] += price.buy[1];
-
-            prices.get_mut(*me).unwrap().update = [true; NUM_ITEM];
-            prices.get_mut(*target).unwrap().update = [true; NUM_ITEM];
+        if prices.get(*me).unwrap().sell[1] < prices.get(*target).unwrap().buy[1] {
+            commands.push(AICommand::Sell(*me, *target, 1));
         }
 
         return true;
     }
+    fn abort(&self, me: &Entity, state: &mut ComponentActionState, _: &AIData, commands: &mut Vec<AICommand>) {
+        abort_move(me, state, commands);
+    }
 }
 
 impl AIAction for AIActionSellIngot {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, me: &Entity, ai_data: &AIData) -> Option<(u8, Option<Entity>, f32)> {
-        let (entities, _, _, axis_datas, _, _, workplaces, stockpiles, _, prices) = ai_data;
+    fn eval(&self, me: &Entity, ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        let (_, _, _, _, _, transforms, workplaces, stockpiles, _, _, spatial, _, _) = ai_data;
 
         let mut out = (8, None, 0.0);
 
         let stockpile = stockpiles.get(*me).unwrap();
 
-        for (target, workplace, _, _) in (*entities, workplaces, prices, stockpiles).join() {
-            if workplace.variant == 3 && stockpile.items[2] >= 1 {
-                let mut weight = 1.0;
-
-                for axis_index in self.axis.iter() {
-                    let axis = &axis_datas[*axis_index as usize];
+        let center = transforms.get(*me).unwrap().translation();
 
-                    let x = clearing_house(&axis.input, me, &target, axis.foo, &ai_data);
-                    let y = response_curve(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
-
-                    weight *= y;
-                }
+        if stockpile.items[2] >= 1 {
+            for target in spatial.get_nearest(center, spatial.candidates, |e| workplaces.get(e).map_or(false, |w| w.variant == 3) && get_quote(me, &e, ai_data).is_some()) {
+                let weight = get_score(8, &self.data, me, &target, ai_data, trace.as_mut().map(|trace| &mut **trace));
 
                 if weight > out.2 {
                     out.1 = Some(target);
@@ -870,8 +844,6 @@ impl AIAction for AIActionSellIngot {
             }
         }
 
-        //println!("{}: {}", self.name, out.2);
-
         if out.2 > 0.0 {
             return Some(out);
         } else {
@@ -879,81 +851,47 @@ impl AIAction for AIActionSellIngot {
         }
     }
 
-    fn init(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, tilemaps, transforms, _, _, movements, _) = ai_data;
-        let tilemap = (tilemaps).join().next().unwrap();
-
-        let me_point = tilemap.to_tile(transforms.get(*me).unwrap().translation(), None).unwrap();
-        let target_point = tilemap.to_tile(transforms.get(*target).unwrap().translation(), None).unwrap();
-
-        if me_point != target_point {
-            let targets = get_targets(&me_point, &target_point, &tilemap);
-
-            if targets.len() > 0 {
-                movements.get_mut(*me).unwrap().targets = targets;
-            } else {
-                return false;
-            }
-        } else {
-            movements.get_mut(*me).unwrap().targets.clear();
-        }
-
-        return true;
+    fn init(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        return init_move_to(me, target, ai_data, commands);
     }
-    fn run(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, _, _, _, stockpiles, movements, prices) = ai_data;
+    fn run(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        let (_, _, _, _, _, _, _, _, movements, prices, _, _, _) = ai_data;
 
         if movements.get(*me).unwrap().targets.len() > 0 {
             return false;
         }
         
-        let stockpile = stockpiles.get_mut(*target).unwrap();
-        let price = prices.get(*target).unwrap();
-
-        if prices.get(*me).unwrap().sell[2] < price.buy[2] && stockpile.items[0] >= price.buy[2] {
-            stockpile.items[2] += 1;
-            stockpile.items[0] -= price.buy[2];
-
-            let stockpile = stockpiles.get_mut(*me).unwrap();
-
-            stockpile.items[2] -= 1;
-            stockpile.items[0] += price.buy[2];
-
-            prices.get_mut(*me).unwrap().update = [true; NUM_ITEM];
-            prices.get_mut(*target).unwrap().update = [true; NUM_ITEM];
+        if prices.get(*me).unwrap().sell[2] < prices.get(*target).unwrap().buy[2] {
+            commands.push(AICommand::Sell(*me, *target, 2));
         }
 
         return true;
     }
+    fn abort(&self, me: &Entity, state: &mut ComponentActionState, _: &AIData, commands: &mut Vec<AICommand>) {
+        abort_move(me, state, commands);
+    }
 }
 
 impl AIAction for AIActionSellTools {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.data.name
     }
-    fn get_delay(&self) -> &HashMap<Entity, u32> {
-        &self.delays
+    fn get_data(&self) -> &AIActionData {
+        &self.data
     }
 
-    fn eval(&self, me: &Entity, ai_data: &AIData) -> Option<(u8, Option<Entity>, f32)> {
-        let (entities, _, _, axis_datas, _, _, workplaces, stockpiles, _, prices) = ai_data;
+    fn eval(&self, me: &Entity, ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+        let (_, _, _, _, _, transforms, workplaces, stockpiles, _, _, spatial, _, _) = ai_data;
 
         let mut out = (9, None, 0.0);
 
         let stockpile = stockpiles.get(*me).unwrap();
 
-        for (target, workplace, _, _) in (*entities, workplaces, prices, stockpiles).join() {
-            if workplace.variant == 3 && stockpile.items[3] >= 1 {
-                let mut weight = 1.0;
+        let center = transforms.get(*me).unwrap().translation();
 
-                for axis_index in self.axis.iter() {
-                    let axis = &axis_datas[*axis_index as usize];
-
-                    let x = clearing_house(&axis.input, me, &target, axis.foo, &ai_data);
-                    let y = response_curve(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
-
-                    weight *= y;
-                }
+        if stockpile.items[3] >= 1 {
+            for target in spatial.get_nearest(center, spatial.candidates, |e| workplaces.get(e).map_or(false, |w| w.variant == 3) && get_quote(me, &e, ai_data).is_some()) {
+                let weight = get_score(9, &self.data, me, &target, ai_data, trace.as_mut().map(|trace| &mut **trace));
 
                 if weight > out.2 {
                     out.1 = Some(target);
@@ -962,8 +900,6 @@ impl AIAction for AIActionSellTools {
             }
         }
 
-        //println!("{}: {}", self.name, out.2);
-
         if out.2 > 0.0 {
             return Some(out);
         } else {
@@ -971,56 +907,62 @@ impl AIAction for AIActionSellTools {
         }
     }
 
-    fn init(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, tilemaps, transforms, _, _, movements, _) = ai_data;
-        let tilemap = (tilemaps).join().next().unwrap();
-
-        let me_point = tilemap.to_tile(transforms.get(*me).unwrap().translation(), None).unwrap();
-        let target_point = tilemap.to_tile(transforms.get(*target).unwrap().translation(), None).unwrap();
-
-        if me_point != target_point {
-            let targets = get_targets(&me_point, &target_point, &tilemap);
-
-            if targets.len() > 0 {
-                movements.get_mut(*me).unwrap().targets = targets;
-            } else {
-                return false;
-            }
-        } else {
-            movements.get_mut(*me).unwrap().targets.clear();
-        }
-
-        return true;
+    fn init(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        return init_move_to(me, target, ai_data, commands);
     }
-    fn run(&mut self, me: &Entity, target: &Entity, ai_data: &mut AIData) -> bool {
-        let (_, _, _, _, _, _, _, stockpiles, movements, prices) = ai_data;
+    fn run(&self, me: &Entity, target: &Entity, _: &mut ComponentActionState, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+        let (_, _, _, _, _, _, _, _, movements, prices, _, _, _) = ai_data;
 
         if movements.get(*me).unwrap().targets.len() > 0 {
             return false;
         }
         
-        let stockpile = stockpiles.get_mut(*target).unwrap();
-        let price = prices.get(*target).unwrap();
+        if prices.get(*me).unwrap().sell[3] < prices.get(*target).unwrap().buy[3] {
+            commands.push(AICommand::Sell(*me, *target, 3));
+        }
 
-        if prices.get(*me).unwrap().sell[3] < price.buy[3] && stockpile.items[0] >= price.buy[3] {
-            stockpile.items[3] += 1;
-            stockpile.items[0] -= price.buy[3];
+        return true;
+    }
+    fn abort(&self, me: &Entity, state: &mut ComponentActionState, _: &AIData, commands: &mut Vec<AICommand>) {
+        abort_move(me, state, commands);
+    }
+}
 
-            let stockpile = stockpiles.get_mut(*me).unwrap();
+// Shared by every action that walks to its target: queues the flow field path to the
+// target's entrance, or fails when the target can't be reached.
+pub fn init_move_to(me: &Entity, target: &Entity, ai_data: &AIData, commands: &mut Vec<AICommand>) -> bool {
+    let (_, _, _, _, chunks, transforms, _, _, _, _, _, travel, _) = ai_data;
 
-            stockpile.items[3] -= 1;
-            stockpile.items[0] += price.buy[3];
+    let me_point = chunks.to_tile(transforms.get(*me).unwrap().translation()).unwrap();
+    let target_point = travel.fields.get(target).map_or_else(|| chunks.to_tile(transforms.get(*target).unwrap().translation()).unwrap(), |field| field.goal);
 
-            prices.get_mut(*me).unwrap().update = [true; NUM_ITEM];
-            prices.get_mut(*target).unwrap().update = [true; NUM_ITEM];
-        }
+    if me_point == target_point {
+        commands.push(AICommand::Move(*me, Vec::new()));
 
         return true;
     }
+
+    let targets = match travel.fields.get(target) {
+        Some(field) => get_flow_targets(&me_point, field),
+        None => Vec::new(),
+    };
+
+    if targets.is_empty() {
+        return false;
+    }
+
+    commands.push(AICommand::Move(*me, targets));
+
+    return true;
+}
+
+pub fn abort_move(me: &Entity, state: &mut ComponentActionState, commands: &mut Vec<AICommand>) {
+    *state = ComponentActionState::default();
+    commands.push(AICommand::Move(*me, Vec::new()));
 }
 
 pub fn clearing_house(variant: &AIInputType, me: &Entity, target: &Entity, foo: f32, ai_data: &AIData) -> f32 {
-    let (entities, workplace_datas, item_datas, _, tilemaps, transforms, workplaces, stockpiles, movements, prices) = ai_data;
+    let (entities, workplace_datas, item_datas, _, chunks, transforms, workplaces, stockpiles, movements, prices, spatial, travel, _) = ai_data;
     
     match variant {
         AIInputType::MyStockpileOre => {
@@ -1038,22 +980,22 @@ pub fn clearing_house(variant: &AIInputType, me: &Entity, target: &Entity, foo:
             return clamp(dist / foo);
         }
         AIInputType::PriceDiffBuyOre => {
-            return clamp((prices.get(*me).unwrap().buy[1] as f32 / prices.get(*target).unwrap().sell[1] as f32) / foo);
+            return get_quote(me, target, ai_data).map_or(0.0, |quote| clamp((prices.get(*me).unwrap().buy[1] as f32 / quote.sell[1] as f32) / foo));
         }
         AIInputType::PriceDiffBuyIngot => {
-            return clamp((prices.get(*me).unwrap().buy[2] as f32 / prices.get(*target).unwrap().sell[2] as f32) / foo);
+            return get_quote(me, target, ai_data).map_or(0.0, |quote| clamp((prices.get(*me).unwrap().buy[2] as f32 / quote.sell[2] as f32) / foo));
         }
         AIInputType::PriceDiffBuyTools => {
-            return clamp((prices.get(*me).unwrap().buy[3] as f32 / prices.get(*target).unwrap().sell[3] as f32) / foo);
+            return get_quote(me, target, ai_data).map_or(0.0, |quote| clamp((prices.get(*me).unwrap().buy[3] as f32 / quote.sell[3] as f32) / foo));
         }
         AIInputType::PriceDiffSellOre => {
-            return clamp((prices.get(*target).unwrap().buy[1] as f32 / prices.get(*me).unwrap().sell[1] as f32) / foo);
+            return get_quote(me, target, ai_data).map_or(0.0, |quote| clamp((quote.buy[1] as f32 / prices.get(*me).unwrap().sell[1] as f32) / foo));
         }
         AIInputType::PriceDiffSellIngot => {
-            return clamp((prices.get(*target).unwrap().buy[2] as f32 / prices.get(*me).unwrap().sell[2] as f32) / foo);
+            return get_quote(me, target, ai_data).map_or(0.0, |quote| clamp((quote.buy[2] as f32 / prices.get(*me).unwrap().sell[2] as f32) / foo));
         }
         AIInputType::PriceDiffSellTools => {
-            return clamp((prices.get(*target).unwrap().buy[3] as f32 / prices.get(*me).unwrap().sell[3] as f32) / foo);
+            return get_quote(me, target, ai_data).map_or(0.0, |quote| clamp((quote.buy[3] as f32 / prices.get(*me).unwrap().sell[3] as f32) / foo));
         }
         AIInputType::CanBuyOre => {
             return clamp((stockpiles.get(*me).unwrap().items[0] as f32 / prices.get(*me).unwrap().buy[1] as f32) / foo);
@@ -1073,25 +1015,597 @@ pub fn clearing_house(variant: &AIInputType, me: &Entity, target: &Entity, foo:
         AIInputType::CanSellTools => {
             return clamp(stockpiles.get(*me).unwrap().items[3] as f32 / foo);
         }
+        AIInputType::CrowdAtTarget => {
+            let center = transforms.get(*target).unwrap().translation();
+            let crowd = spatial.get_within(center, spatial.size, |e| e != *me && movements.contains(e));
+            return clamp(crowd.len() as f32 / foo);
+        }
+        AIInputType::TravelTime => {
+            if let Some(field) = travel.fields.get(target) {
+                if let Some(point) = chunks.to_tile(transforms.get(*me).unwrap().translation()) {
+                    if let Some(distance) = field.get_distance(&point) {
+                        return clamp(distance as f32 / foo);
+                    }
+                }
+            }
+
+            return 1.0;
+        }
+    }
+}
+
+pub fn get_score(action: u8, action_data: &AIActionData, me: &Entity, target: &Entity, ai_data: &AIData, trace: Option<&mut Vec<AIScore>>) -> f32 {
+    let (_, _, _, axis_datas, _, _, _, _, _, _, _, _, _) = ai_data;
+
+    let mut weight = 1.0;
+    let mut axes = Vec::new();
+
+    for axis_index in action_data.axis.iter() {
+        let axis = &axis_datas[*axis_index as usize];
+
+        let x = clearing_house(&axis.input, me, target, axis.foo, ai_data);
+        let mut y = axis.get_output(x);
+
+        if action_data.compensate {
+            y = compensate(y, action_data.axis.len());
+        }
+
+        if trace.is_some() {
+            axes.push(AIAxisScore { axis: *axis_index, input: x, output: y });
+        }
+
+        weight *= y;
+    }
+
+    weight *= action_data.weight;
+
+    if weight < action_data.cutoff {
+        weight = 0.0;
+    }
+
+    if let Some(trace) = trace {
+        trace.push(AIScore { action, target: Some(target.id()), axes, weight });
+    }
+
+    return weight;
+}
+
+// Agents with a ComponentMemory only know the markets they have visited or heard about,
+// everything else still sees the live prices and stock.
+pub fn get_quote(me: &Entity, target: &Entity, ai_data: &AIData) -> Option<AIMemory> {
+    let (_, _, _, _, _, _, _, stockpiles, _, prices, _, _, memories) = ai_data;
+
+    if let Some(memory) = memories.get(*me) {
+        return memory.markets.get(target).cloned();
+    }
+
+    let price = prices.get(*target)?;
+    let stockpile = stockpiles.get(*target)?;
+
+    return Some(AIMemory { buy: price.buy, sell: price.sell, stock: stockpile.items, time: 0 });
+}
+
+pub fn remember(markets: &mut HashMap<Entity, AIMemory>, market: Entity, memory: AIMemory) -> bool {
+    if markets.get(&market).map_or(false, |known| known.time > memory.time) {
+        return false;
+    }
+
+    markets.insert(market, memory);
+
+    return true;
+}
+
+// Memories start empty and only grow from the markets in sight or from gossip, `quote`
+// returns what can be seen of a market right now.
+pub fn observe<F: Fn(Entity) -> Option<AIMemory>>(markets: &mut HashMap<Entity, AIMemory>, center: &Vector3<f32>, spatial: &MiscSpatialGrid, quote: F) {
+    for market in spatial.get_within(center, MEMORY_SIGHT, |_| true) {
+        if let Some(memory) = quote(market) {
+            remember(markets, market, memory);
+        }
+    }
+}
+
+pub enum AICommand {
+    Move(Entity, Vec<Point3<u32>>),
+    Produce(Entity, u8, usize),
+    Buy(Entity, Entity, usize),
+    Sell(Entity, Entity, usize),
+}
+
+pub fn decide<R: Rng>(entity: &Entity, agent: &mut ComponentAgent, log: Option<&mut ComponentDecisionLog>, archetype: Option<&AIArchetypeData>, action_datas: &[Box<dyn AIAction>], ai_data: &AIData, time: &MiscTime, rng: &mut R) {
+    if agent.actions.iter().all(|action| *action == 255) {
+        return;
+    }
+
+    if agent.current != 255 {
+        agent.timer += 1;
+
+        match archetype {
+            Some(archetype) if archetype.goal.is_none() && archetype.reevaluate > 0 && agent.timer >= archetype.reevaluate => (),
+            _ => return,
+        }
+    }
+
+    agent.timer = 0;
+
+    let mut trace = log.as_ref().map(|_| Vec::new());
+
+    let planned = match archetype {
+        Some(archetype) => get_planned(entity, agent, archetype, action_datas, ai_data, trace.as_mut()),
+        None => None,
+    };
+
+    let current = match planned {
+        Some(current) => current,
+        None => {
+            let mut evals: Vec<(u8, Option<Entity>, f32)> = Vec::new();
+
+            for action in agent.actions.iter() {
+                if *action == 255 {
+                    continue;
+                }
+
+                if let Some(eval) = action_datas[*action as usize].eval(entity, ai_data, trace.as_mut()) {
+                    evals.push(eval);
+                }
+            }
+
+            if agent.current != 255 {
+                let momentum = archetype.map_or(1.0, |archetype| archetype.momentum);
+
+                for eval in evals.iter_mut().filter(|eval| eval.0 == agent.current && eval.1 == agent.target) {
+                    eval.2 *= momentum;
+                }
+            }
+
+            let weights: Vec<(f32, u8)> = evals.iter().map(|eval| (eval.2, action_datas[eval.0 as usize].get_data().priority)).collect();
+
+            let selection = archetype.map_or(&SELECTION_DEFAULT, |archetype| &archetype.selection);
+
+            match select(selection, &weights, rng) {
+                Some(i) => evals[i],
+                None if evals.iter().any(|eval| eval.0 == agent.current && eval.1 == agent.target) => return,
+                None => (ACTION_IDLE, None, 0.0),
+            }
+        }
+    };
+
+    if let (Some(log), Some(scores)) = (log, trace) {
+        log.decisions.push_back(AIDecision {
+            time: format!("{}/{}/{} {}:{}:{}", time.year, time.month, time.day, time.hour, time.mnt, time.scnd),
+            scores,
+            action: current.0,
+            target: current.1.map(|target| target.id()),
+            weight: current.2,
+        });
+
+        while log.decisions.len() > log.limit {
+            log.decisions.pop_front();
+        }
+    }
+
+    if agent.current != 255 {
+        if current.0 == agent.current && current.1 == agent.target {
+            return;
+        }
+
+        agent.aborted = Some(agent.current);
+    }
+
+    agent.current = current.0;
+    agent.target = current.1;
+    agent.fresh = true;
+}
+
+pub fn get_planned(entity: &Entity, agent: &mut ComponentAgent, archetype: &AIArchetypeData, action_datas: &[Box<dyn AIAction>], ai_data: &AIData, mut trace: Option<&mut Vec<AIScore>>) -> Option<(u8, Option<Entity>, f32)> {
+    let goal = archetype.goal.as_ref()?;
+
+    if agent.plan.is_empty() {
+        let (_, workplace_datas, _, _, _, transforms, workplaces, stockpiles, _, _, spatial, _, _) = ai_data;
+
+        let center = transforms.get(*entity).unwrap().translation();
+        let market = spatial.get_nearest(center, 1, |e| workplaces.get(e).map_or(false, |w| w.variant == 3) && get_quote(entity, &e, ai_data).is_some()).into_iter().next();
+        let (buy, sell) = market.and_then(|market| get_quote(entity, &market, ai_data)).map_or(([0; NUM_ITEM], [0; NUM_ITEM]), |quote| (quote.sell, quote.buy));
+
+        let planner = AIPlanner {
+            goal,
+            steps: agent.actions.iter().filter(|action| **action != 255).filter_map(|action| action_datas[*action as usize].get_data().effect.map(|effect| (*action, effect))).collect(),
+            workplace_datas: workplace_datas.as_slice(),
+            buy,
+            sell,
+        };
+
+        agent.plan = planner.plan(stockpiles.get(*entity)?.items, archetype.depth).into_iter().collect();
+    }
+
+    while let Some(step) = agent.plan.pop_front() {
+        match action_datas[step as usize].eval(entity, ai_data, trace.as_mut().map(|trace| &mut **trace)) {
+            Some(eval) => return Some(eval),
+            None => agent.plan.clear(),
+        }
+    }
+
+    return None;
+}
+
+pub fn get_rng(entity: &Entity, tick: u64) -> StdRng {
+    return StdRng::seed_from_u64(tick.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ entity.id() as u64);
+}
+
+pub fn apply(command: AICommand, ai_data: &mut AIData) -> bool {
+    let (_, workplace_datas, _, _, _, _, _, stockpiles, movements, prices, _, _, _) = ai_data;
+
+    match command {
+        AICommand::Move(entity, targets) => {
+            if let Some(movement) = movements.get_mut(entity) {
+                movement.targets = targets;
+            }
+
+            return true;
+        }
+        AICommand::Produce(entity, variant, item) => {
+            let workplace_data = &workplace_datas[variant as usize];
+            let stockpile = stockpiles.get_mut(entity).unwrap();
+
+            if workplace_data.inputs.iter().enumerate().any(|(i, input)| stockpile.items[i] < *input as u16) {
+                return false;
+            }
+
+            for (i, out) in workplace_data.outs.iter().enumerate() {
+                stockpile.items[i] += *out as u16;
+            }
+            for (i, input) in workplace_data.inputs.iter().enumerate() {
+                stockpile.items[i] -= *input as u16;
+            }
+
+            prices.get_mut(entity).unwrap().update[item] = true;
+
+            return true;
+        }
+        AICommand::Buy(buyer, seller, item) => {
+            return trade_buy(&buyer, &seller, item, stockpiles, prices);
+        }
+        AICommand::Sell(seller, buyer, item) => {
+            return trade_sell(&seller, &buyer, item, stockpiles, prices);
+        }
+    }
+}
+
+// Commands run in order and the first failure aborts the running action, a finished action
+// only goes back to Idle once its final commands have gone through.
+pub fn resolve(agent: &mut ComponentAgent, finished: bool, commands: Vec<AICommand>, ai_data: &mut AIData) -> bool {
+    for command in commands.into_iter() {
+        if !apply(command, ai_data) {
+            if agent.current != 255 {
+                agent.aborted = Some(agent.current);
+            }
+
+            agent.current = 255;
+            agent.plan.clear();
+
+            return false;
+        }
+    }
+
+    if finished {
+        agent.current = 255;
+    }
+
+    return true;
+}
+
+pub fn trade_buy(buyer: &Entity, seller: &Entity, item: usize, stockpiles: &mut WriteStorage<ComponentStockpile>, prices: &mut WriteStorage<ComponentPrice>) -> bool {
+    let price = prices.get(*seller).unwrap().sell[item];
+
+    if stockpiles.get(*seller).unwrap().items[item] < 1 || stockpiles.get(*buyer).unwrap().items[0] < price {
+        return false;
+    }
+
+    let stockpile = stockpiles.get_mut(*seller).unwrap();
+
+    stockpile.items[item] -= 1;
+    stockpile.items[0] += price;
+
+    let stockpile = stockpiles.get_mut(*buyer).unwrap();
+
+    stockpile.items[item] += 1;
+    stockpile.items[0] -= price;
+
+    for entity in [buyer, seller].iter() {
+        if let Some(price) = prices.get_mut(**entity) {
+            price.update = [true; NUM_ITEM];
+        }
+    }
+
+    return true;
+}
+
+pub fn trade_sell(seller: &Entity, buyer: &Entity, item: usize, stockpiles: &mut WriteStorage<ComponentStockpile>, prices: &mut WriteStorage<ComponentPrice>) -> bool {
+    let price = prices.get(*buyer).unwrap().buy[item];
+
+    if stockpiles.get(*seller).unwrap().items[item] < 1 || stockpiles.get(*buyer).unwrap().items[0] < price {
+        return false;
+    }
+
+    let stockpile = stockpiles.get_mut(*buyer).unwrap();
+
+    stockpile.items[item] += 1;
+    stockpile.items[0] -= price;
+
+    let stockpile = stockpiles.get_mut(*seller).unwrap();
+
+    stockpile.items[item] -= 1;
+    stockpile.items[0] += price;
+
+    for entity in [buyer, seller].iter() {
+        if let Some(price) = prices.get_mut(**entity) {
+            price.update = [true; NUM_ITEM];
+        }
+    }
+
+    return true;
+}
+
+pub const NUM_ACTION: usize = 10;
+
+pub fn get_actions(action_datas: Vec<AIActionData>) -> Vec<Box<dyn AIAction>> {
+    let mut action_datas = action_datas.into_iter();
+    let mut actions: Vec<Box<dyn AIAction>> = Vec::new();
+
+    actions.push(Box::new(AIActionIdle { data: action_datas.next().unwrap() }));
+    actions.push(Box::new(AIActionWorkAtMine { data: action_datas.next().unwrap() }));
+    actions.push(Box::new(AIActionWorkAtFurnace { data: action_datas.next().unwrap() }));
+    actions.push(Box::new(AIActionWorkAtSmithy { data: action_datas.next().unwrap() }));
+    actions.push(Box::new(AIActionBuyOre { data: action_datas.next().unwrap() }));
+    actions.push(Box::new(AIActionBuyIngot { data: action_datas.next().unwrap() }));
+    actions.push(Box::new(AIActionBuyTools { data: action_datas.next().unwrap() }));
+    actions.push(Box::new(AIActionSellOre { data: action_datas.next().unwrap() }));
+    actions.push(Box::new(AIActionSellIngot { data: action_datas.next().unwrap() }));
+    actions.push(Box::new(AIActionSellTools { data: action_datas.next().unwrap() }));
+
+    return actions;
+}
+
+pub fn select<R: Rng>(selection: &AISelection, scores: &[(f32, u8)], rng: &mut R) -> Option<usize> {
+    let candidates: Vec<usize> = (0..scores.len()).filter(|i| scores[*i].0.is_finite() && scores[*i].0 > 0.0).collect();
+
+    if candidates.is_empty() {
+        return None;
+    }
+
+    let best = *candidates.iter().max_by(|a, b| scores[**a].0.partial_cmp(&scores[**b].0).unwrap()).unwrap();
+
+    match selection {
+        AISelection::Argmax => {
+            return Some(best);
+        }
+        AISelection::Weighted(power) => {
+            return Some(sample(&candidates, |i| scores[i].0.powf(*power), rng).unwrap_or(best));
+        }
+        AISelection::TopN(n) => {
+            let mut top = candidates.clone();
+            top.sort_by(|a, b| scores[*b].0.partial_cmp(&scores[*a].0).unwrap());
+            top.truncate((*n).max(1));
+
+            return Some(sample(&top, |i| scores[i].0, rng).unwrap_or(best));
+        }
+        AISelection::Softmax(temperature) => {
+            if *temperature <= 0.0 || !temperature.is_finite() {
+                return Some(best);
+            }
+
+            let max = scores[best].0;
+
+            return Some(sample(&candidates, |i| ((scores[i].0 - max) / temperature).exp(), rng).unwrap_or(best));
+        }
+        AISelection::Bucketed => {
+            let priority = candidates.iter().map(|i| scores[*i].1).max().unwrap();
+            let bucket: Vec<usize> = candidates.iter().cloned().filter(|i| scores[*i].1 == priority).collect();
+
+            return Some(sample(&bucket, |i| scores[i].0, rng).unwrap_or(best));
+        }
+    }
+}
+
+fn sample<R: Rng, F: Fn(usize) -> f32>(candidates: &[usize], weight: F, rng: &mut R) -> Option<usize> {
+    match WeightedIndex::new(candidates.iter().map(|i| weight(*i))) {
+        Ok(dist) => return Some(candidates[dist.sample(rng)]),
+        Err(_) => return None,
+    }
+}
+
+pub fn check_archetype(archetype: &AIArchetypeData) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    match archetype.selection {
+        AISelection::Weighted(power) if !power.is_finite() || power <= 0.0 => {
+            errors.push(format!("{}: Weighted power must be positive, got {}", archetype.name, power));
+        }
+        AISelection::TopN(0) => {
+            errors.push(format!("{}: TopN needs at least one candidate", archetype.name));
+        }
+        AISelection::Softmax(temperature) if !temperature.is_finite() || temperature <= 0.0 => {
+            errors.push(format!("{}: Softmax temperature must be positive, got {}", archetype.name, temperature));
+        }
+        _ => (),
+    }
+    if archetype.depth > 6 {
+        errors.push(format!("{}: planning depth {} is too deep, at most 6 is supported", archetype.name, archetype.depth));
+    }
+    if let Some(AIGoal::Stock(item, _)) = archetype.goal {
+        if item >= NUM_ITEM {
+            errors.push(format!("{}: goal item {} is out of range", archetype.name, item));
+        }
+    }
+    if !archetype.momentum.is_finite() || archetype.momentum < 1.0 {
+        errors.push(format!("{}: momentum must be at least 1, got {}", archetype.name, archetype.momentum));
+    }
+
+    return errors;
+}
+
+pub fn compensate(score: f32, count: usize) -> f32 {
+    if count == 0 {
+        return score;
+    }
+
+    let modification = 1.0 - 1.0 / count as f32;
+
+    return score + (1.0 - score) * modification * score;
+}
+
+pub fn check_action(action: &AIActionData, axis_count: usize, workplace_count: usize) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    match action.effect {
+        Some(AIEffect::Work(variant)) if variant as usize >= workplace_count => {
+            errors.push(format!("{}: workplace {} is out of range, {} workplaces defined", action.name, variant, workplace_count));
+        }
+        Some(AIEffect::Buy(item)) | Some(AIEffect::Sell(item)) if item >= NUM_ITEM => {
+            errors.push(format!("{}: item {} is out of range, {} items defined", action.name, item, NUM_ITEM));
+        }
+        _ => (),
+    }
+
+    for axis in action.axis.iter().filter(|axis| **axis as usize >= axis_count) {
+        errors.push(format!("{}: axis {} is out of range, {} axes defined", action.name, axis, axis_count));
+    }
+    if !action.weight.is_finite() || action.weight < 0.0 {
+        errors.push(format!("{}: weight must be a non-negative number, got {}", action.name, action.weight));
+    }
+    if !(0.0..=1.0).contains(&action.cutoff) {
+        errors.push(format!("{}: cutoff must be within 0 and 1, got {}", action.name, action.cutoff));
+    }
+
+    return errors;
+}
+
+pub fn check_axis(axis: &AIAxis) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for (i, (name, _)) in AXIS_PARAMS.iter().enumerate() {
+        if !axis.get_param(i).is_finite() {
+            errors.push(format!("{}: {} is not finite", axis.name, name));
+        }
+    }
+    if axis.foo <= 0.0 {
+        errors.push(format!("{}: foo must be positive, got {}", axis.name, axis.foo));
+    }
+
+    return errors;
+}
+
+pub fn check_curve(axis: &AIAxis) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    match &axis.curve {
+        AICurveType::Logit if axis.k == 0.0 => {
+            errors.push(format!("{}: Logit with k 0 is undefined", axis.name));
+        }
+        AICurveType::Normal if axis.k == 0.0 => {
+            errors.push(format!("{}: Normal with k 0 has no width", axis.name));
+        }
+        AICurveType::Piecewise(points) if points.is_empty() => {
+            errors.push(format!("{}: Piecewise has no points", axis.name));
+        }
+        AICurveType::Piecewise(points) if points.windows(2).any(|pair| pair[0].0 > pair[1].0) => {
+            errors.push(format!("{}: Piecewise points are not sorted by x", axis.name));
+        }
+        _ => (),
+    }
+    if let Some((min, max)) = axis.range {
+        if min > max {
+            errors.push(format!("{}: range ({}, {}) is empty", axis.name, min, max));
+        }
+    }
+    if !errors.is_empty() {
+        return errors;
+    }
+
+    for i in 0..=20 {
+        let x = i as f32 / 20.0;
+        let y = response_raw(&axis.curve, x, axis.m, axis.k, axis.b, axis.c);
+
+        if !y.is_finite() {
+            errors.push(format!("{}: response curve gives {} at x = {}", axis.name, y, x));
+            break;
+        }
     }
+
+    return errors;
 }
 
 pub fn response_curve(variant: &AICurveType, x: f32, m: f32, k: f32, b: f32, c: f32) -> f32 {
+    return clamp(response_raw(variant, x, m, k, b, c));
+}
+
+pub fn response_raw(variant: &AICurveType, x: f32, m: f32, k: f32, b: f32, c: f32) -> f32 {
     match variant {
         AICurveType::Quadratic => {
-            return clamp(m * (x - c).powf(k) + b);
+            return m * (x - c).powf(k) + b;
         },
         AICurveType::Logistic => {
-            return clamp(k / (1.0 + E.powf(m * (c - x))) + b);
+            return k / (1.0 + E.powf(m * (c - x))) + b;
         }
         AICurveType::Logit => {
-            return clamp((k * (x - c) / (1.0 - x + c)).ln() * m + b);
+            return (k * (x - c) / (1.0 - x + c)).ln() * m + b;
+        }
+        AICurveType::Exponential => {
+            return m * E.powf(k * (x - c)) + b;
+        }
+        AICurveType::Sine => {
+            return m * (k * PI * (x - c)).sin() + b;
+        }
+        AICurveType::Step => {
+            if x >= c {
+                return m + b;
+            } else {
+                return b;
+            }
+        }
+        AICurveType::Normal => {
+            return m * E.powf(-(x - c).powf(2.0) / (2.0 * k.powf(2.0))) + b;
+        }
+        AICurveType::Inverse => {
+            return m / (x - c) + b;
+        }
+        AICurveType::Piecewise(points) => {
+            return piecewise(points, x);
+        }
+    }
+}
+
+pub fn piecewise(points: &[(f32, f32)], x: f32) -> f32 {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return 0.0,
+    };
+
+    if x <= first.0 {
+        return first.1;
+    }
+    if x >= last.0 {
+        return last.1;
+    }
+
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+
+        if x >= x0 && x <= x1 {
+            if x1 - x0 <= 0.0 {
+                return y1;
+            }
+            return y0 + (y1 - y0) * (x - x0) / (x1 - x0);
         }
     }
+
+    return last.1;
 }
 
 pub fn clamp(x: f32) -> f32 {
-    if x > 1.0 {
+    if x.is_nan() {
+        return 0.0;
+    } else if x > 1.0 {
         return 1.0;
     } else if x < 0.0 {
         return 0.0;
@@ -1110,5 +1624,308 @@ mod tests {
         assert_eq!(clamp(2.0), 1.0);
         assert_eq!(clamp(0.123), 0.123);
         assert_eq!(clamp(0.987), 0.987);
+        assert_eq!(clamp(std::f32::NAN), 0.0);
+        assert_eq!(clamp(std::f32::INFINITY), 1.0);
+        assert_eq!(clamp(std::f32::NEG_INFINITY), 0.0);
+    }
+
+    #[test]
+    fn test_curves() {
+        let curves = vec![
+            AICurveType::Quadratic,
+            AICurveType::Logistic,
+            AICurveType::Logit,
+            AICurveType::Exponential,
+            AICurveType::Sine,
+            AICurveType::Step,
+            AICurveType::Normal,
+            AICurveType::Inverse,
+            AICurveType::Piecewise(vec![(0.2, 0.0), (0.8, 1.0)]),
+            AICurveType::Piecewise(Vec::new()),
+        ];
+
+        for curve in curves.iter() {
+            for (m, k, b, c) in [(1.0, 1.0, 0.0, 0.0), (-1.0, 0.5, 1.0, 0.5), (0.0, 0.0, 0.0, 0.0), (1.0, 0.3, 0.0, 1.0)].iter() {
+                for i in 0..=20 {
+                    let y = response_curve(curve, i as f32 / 20.0, *m, *k, *b, *c);
+                    assert!((0.0..=1.0).contains(&y), "{:?} gave {} at {}", curve, y, i);
+                }
+            }
+        }
+
+        assert_eq!(response_curve(&AICurveType::Quadratic, 0.5, 1.0, 2.0, 0.0, 0.0), 0.25);
+        assert_eq!(response_curve(&AICurveType::Logistic, 0.5, 10.0, 1.0, 0.0, 0.5), 0.5);
+        assert!((response_curve(&AICurveType::Logit, 0.5, 0.1, 1.0, 0.5, 0.0) - 0.5).abs() < 1e-6);
+        assert_eq!(response_curve(&AICurveType::Exponential, 0.0, 0.5, 1.0, 0.0, 0.0), 0.5);
+        assert!((response_curve(&AICurveType::Sine, 0.5, 1.0, 1.0, 0.0, 0.0) - 1.0).abs() < 1e-6);
+        assert_eq!(response_curve(&AICurveType::Step, 0.4, 1.0, 0.0, 0.0, 0.5), 0.0);
+        assert_eq!(response_curve(&AICurveType::Step, 0.6, 1.0, 0.0, 0.0, 0.5), 1.0);
+        assert_eq!(response_curve(&AICurveType::Normal, 0.5, 1.0, 0.1, 0.0, 0.5), 1.0);
+        assert_eq!(response_curve(&AICurveType::Inverse, 0.5, 0.25, 0.0, 0.0, 0.0), 0.5);
+        assert_eq!(response_curve(&AICurveType::Inverse, 0.0, 0.25, 0.0, 0.0, 0.0), 1.0);
+        assert!((response_curve(&AICurveType::Piecewise(vec![(0.2, 0.0), (0.8, 1.0)]), 0.5, 0.0, 0.0, 0.0, 0.0) - 0.5).abs() < 1e-6);
+        assert_eq!(response_curve(&AICurveType::Piecewise(vec![(0.2, 0.0), (0.8, 1.0)]), 0.1, 0.0, 0.0, 0.0, 0.0), 0.0);
+        assert_eq!(response_curve(&AICurveType::Piecewise(vec![(0.2, 0.0), (0.8, 1.0)]), 0.9, 0.0, 0.0, 0.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let mut axis = AIAxis { name: "Test".to_string(), curve: AICurveType::Quadratic, input: AIInputType::MyStockpileOre, foo: 1.0, m: 1.0, k: 1.0, b: 0.0, c: 0.0, invert: false, range: None };
+        assert_eq!(axis.get_output(0.25), 0.25);
+
+        axis.invert = true;
+        assert_eq!(axis.get_output(0.25), 0.75);
+
+        axis.range = Some((0.1, 0.5));
+        assert_eq!(axis.get_output(0.25), 0.5);
+        assert_eq!(axis.get_output(1.0), 0.1);
+    }
+
+    #[test]
+    fn test_set_param() {
+        let mut axis = AIAxis { name: "Test".to_string(), curve: AICurveType::Quadratic, input: AIInputType::MyStockpileOre, foo: 1.0, m: 1.0, k: 2.0, b: 0.0, c: 0.0, invert: false, range: None };
+
+        assert!(axis.set_param(4, -1.0));
+        assert_eq!(axis.foo, 0.05);
+
+        assert!(axis.set_param(0, 100.0));
+        assert_eq!(axis.m, 20.0);
+
+        assert!(axis.set_param(3, 0.5));
+        assert!(!axis.set_param(1, 1.5));
+        assert_eq!(axis.k, 2.0);
+
+        axis.curve = AICurveType::Normal;
+        assert!(axis.set_param(1, 0.0));
+        assert_eq!(axis.k, 0.05);
+    }
+
+    #[test]
+    fn test_select() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let scores = vec![(0.2, 0), (0.9, 0), (0.5, 1), (0.0, 2)];
+
+        assert_eq!(select(&AISelection::Argmax, &scores, &mut rng), Some(1));
+        assert_eq!(select(&AISelection::TopN(1), &scores, &mut rng), Some(1));
+        assert_eq!(select(&AISelection::Bucketed, &scores, &mut rng), Some(2));
+        assert_eq!(select(&AISelection::Softmax(0.0), &scores, &mut rng), Some(1));
+
+        for selection in [AISelection::Weighted(5.0), AISelection::TopN(2), AISelection::Softmax(0.1)].iter() {
+            for _ in 0..100 {
+                let i = select(selection, &scores, &mut rng).unwrap();
+                assert!(i != 3, "{:?} picked a zero score", selection);
+            }
+        }
+
+        let empty = vec![(0.0, 0), (std::f32::NAN, 0)];
+        for selection in [AISelection::Argmax, AISelection::Weighted(5.0), AISelection::TopN(2), AISelection::Softmax(0.1), AISelection::Bucketed].iter() {
+            assert_eq!(select(selection, &empty, &mut rng), None);
+            assert_eq!(select(selection, &[], &mut rng), None);
+        }
+
+        assert_eq!(select(&AISelection::Weighted(500.0), &[(0.1, 0), (0.2, 0)], &mut rng), Some(1));
+    }
+
+    #[test]
+    fn test_planner() {
+        let workplace = |name: &str, inputs: Vec<u8>, outs: Vec<u8>| AssetWorkplaceData {
+            name: name.to_string(), inputs, outs, duration: 60, terrain: Vec::new(), size: (2, 2), entrance: (0, 1), cost: vec![0; NUM_ITEM],
+        };
+        let workplace_datas = vec![
+            workplace("Mine", vec![0, 0, 0, 1], vec![0, 1, 0, 0]),
+            workplace("Furnace", vec![0, 1, 0, 0], vec![0, 0, 1, 0]),
+            workplace("Smithy", vec![0, 0, 1, 0], vec![0, 0, 0, 1]),
+        ];
+        let steps = vec![
+            (1, AIEffect::Work(0)),
+            (2, AIEffect::Work(1)),
+            (3, AIEffect::Work(2)),
+            (6, AIEffect::Buy(3)),
+            (7, AIEffect::Sell(1)),
+            (8, AIEffect::Sell(2)),
+        ];
+
+        let goal = AIGoal::Money;
+        let planner = AIPlanner { goal: &goal, steps: steps.clone(), workplace_datas: &workplace_datas, buy: [0, 0, 0, 10], sell: [0, 5, 30, 0] };
+
+        assert_eq!(planner.plan([20, 0, 0, 0], 4), vec![6, 1, 2, 8]);
+        assert_eq!(planner.plan([20, 0, 0, 0], 2), Vec::<u8>::new());
+        assert_eq!(planner.plan([0, 0, 0, 0], 4), Vec::<u8>::new());
+
+        let goal = AIGoal::Stock(3, 2);
+        let planner = AIPlanner { goal: &goal, steps, workplace_datas: &workplace_datas, buy: [0, 0, 0, 10], sell: [0, 5, 30, 0] };
+
+        assert_eq!(planner.plan([20, 0, 0, 0], 4), vec![6, 6]);
+        assert_eq!(planner.plan([5, 0, 1, 0], 4), vec![8, 6, 6]);
+        assert_eq!(planner.plan([5, 0, 1, 0], 2), vec![3]);
+    }
+
+    #[test]
+    fn test_remember() {
+        let market = get_test_entities(1)[0];
+
+        let memory = |price: u16, time: u64| AIMemory { buy: [price; NUM_ITEM], sell: [price; NUM_ITEM], stock: [1; NUM_ITEM], time };
+        let mut markets = HashMap::new();
+
+        assert!(remember(&mut markets, market, memory(10, 5)));
+        assert!(!remember(&mut markets, market, memory(20, 4)));
+        assert_eq!(markets[&market].buy[1], 10);
+
+        assert!(remember(&mut markets, market, memory(30, 5)));
+        assert!(remember(&mut markets, market, memory(40, 6)));
+        assert_eq!(markets[&market].buy[1], 40);
+        assert_eq!(markets[&market].time, 6);
+    }
+
+    #[test]
+    fn test_observe() {
+        let entities = get_test_entities(3);
+        let (near, far, other) = (entities[0], entities[1], entities[2]);
+
+        let mut spatial = MiscSpatialGrid::default();
+
+        spatial.insert(near, &Vector3::new(2.0, 0.0, 0.0));
+        spatial.insert(far, &Vector3::new(MEMORY_SIGHT + 5.0, 0.0, 0.0));
+        spatial.insert(other, &Vector3::new(1.0, 0.0, 0.0));
+
+        let quote = |market: Entity| if market == other { None } else { Some(AIMemory { buy: [10; NUM_ITEM], sell: [5; NUM_ITEM], stock: [1; NUM_ITEM], time: 3 }) };
+        let mut memory = ComponentMemory::default();
+
+        assert!(memory.markets.is_empty());
+
+        observe(&mut memory.markets, &Vector3::new(0.0, 0.0, 0.0), &spatial, quote);
+
+        assert_eq!(memory.markets.len(), 1);
+        assert_eq!(memory.markets[&near].time, 3);
+        assert!(!memory.markets.contains_key(&far));
+    }
+
+    #[test]
+    fn test_rng() {
+        let entities = get_test_entities(2);
+        let scores = [(0.5, 0), (0.4, 0), (0.3, 0), (0.2, 0)];
+        let picks = |entity: &Entity| (0..32).map(|tick| select(&AISelection::Weighted(1.0), &scores, &mut get_rng(entity, tick))).collect::<Vec<Option<usize>>>();
+
+        assert_eq!(picks(&entities[0]), picks(&entities[0]));
+        assert_ne!(picks(&entities[0]), picks(&entities[1]));
+        assert!(picks(&entities[0]).iter().any(|pick| *pick != Some(0)));
+    }
+
+    #[test]
+    fn test_resolve() {
+        use amethyst::ecs::{ World, WorldExt, Builder };
+        use std::collections::VecDeque;
+
+        let mut world = World::new();
+
+        world.register::<Transform>();
+        world.register::<ComponentWorkplace>();
+        world.register::<ComponentStockpile>();
+        world.register::<ComponentMovement>();
+        world.register::<ComponentPrice>();
+        world.register::<ComponentMemory>();
+
+        world.insert(Vec::<AssetWorkplaceData>::new());
+        world.insert(Vec::<AssetItemData>::new());
+        world.insert(Vec::<AIAxis>::new());
+        world.insert(MiscChunks::new(4, 4, 4, None));
+        world.insert(MiscSpatialGrid::default());
+        world.insert(MiscTravel::default());
+
+        let price = || ComponentPrice { update: [false; NUM_ITEM], buy: [10; NUM_ITEM], sell: [5; NUM_ITEM], weight: [1.0; NUM_ITEM], decay: [0.5; NUM_ITEM] };
+        let buyer = world.create_entity().with(ComponentStockpile { items: [100, 0, 0, 0] }).with(price()).build();
+        let seller = world.create_entity().with(ComponentStockpile { items: [0, 0, 0, 0] }).with(price()).build();
+
+        let agent = |plan: Vec<u8>| ComponentAgent { actions: [255; 23], archetype: 0, current: 4, target: Some(seller), fresh: false, timer: 0, aborted: None, plan: VecDeque::from(plan) };
+
+        let entities = world.entities();
+        let (workplace_datas, item_datas, axis_datas, chunks, transforms, workplaces, stockpiles, movements, prices, spatial, travel, memories) = world.system_data::<(
+            Read<Vec<AssetWorkplaceData>>,
+            Read<Vec<AssetItemData>>,
+            Read<Vec<AIAxis>>,
+            ReadExpect<MiscChunks>,
+            WriteStorage<Transform>,
+            WriteStorage<ComponentWorkplace>,
+            WriteStorage<ComponentStockpile>,
+            WriteStorage<ComponentMovement>,
+            WriteStorage<ComponentPrice>,
+            Read<MiscSpatialGrid>,
+            Read<MiscTravel>,
+            ReadStorage<ComponentMemory>,
+        )>();
+        let mut ai_data = (&entities, workplace_datas, item_datas, axis_datas, chunks, transforms, workplaces, stockpiles, movements, prices, spatial, travel, memories);
+
+        let mut empty = agent(vec![7]);
+
+        assert!(!resolve(&mut empty, true, vec![AICommand::Buy(buyer, seller, 1)], &mut ai_data));
+        assert_eq!(empty.aborted, Some(4));
+        assert_eq!(empty.current, 255);
+        assert!(empty.plan.is_empty());
+        assert_eq!(ai_data.7.get(buyer).unwrap().items, [100, 0, 0, 0]);
+
+        ai_data.7.get_mut(seller).unwrap().items[1] = 1;
+
+        let mut stocked = agent(vec![7]);
+
+        assert!(resolve(&mut stocked, true, vec![AICommand::Buy(buyer, seller, 1)], &mut ai_data));
+        assert_eq!(stocked.aborted, None);
+        assert_eq!(stocked.current, 255);
+        assert_eq!(stocked.plan.len(), 1);
+        assert_eq!(ai_data.7.get(buyer).unwrap().items, [95, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_compensate() {
+        assert_eq!(compensate(0.5, 0), 0.5);
+        assert_eq!(compensate(0.5, 1), 0.5);
+        assert_eq!(compensate(0.5, 4), 0.6875);
+        assert_eq!(compensate(1.0, 4), 1.0);
+        assert_eq!(compensate(0.0, 4), 0.0);
+
+        let three = compensate(0.8, 3).powf(3.0);
+        let four = compensate(0.8, 4).powf(4.0);
+        assert!((three - four).abs() < 0.8f32.powf(3.0) - 0.8f32.powf(4.0));
+    }
+
+    #[test]
+    fn test_check_action() {
+        let mut action = AIActionData { name: "Test".to_string(), axis: vec![0, 2], weight: 1.0, cutoff: 0.0, compensate: true, priority: 0, effect: Some(AIEffect::Work(3)) };
+        assert!(check_action(&action, 3, 4).is_empty());
+        assert_eq!(check_action(&action, 2, 3).len(), 2);
+
+        action.effect = Some(AIEffect::Buy(NUM_ITEM));
+        assert_eq!(check_action(&action, 3, 4).len(), 1);
+
+        action.effect = Some(AIEffect::Sell(NUM_ITEM - 1));
+        assert!(check_action(&action, 3, 4).is_empty());
+    }
+
+    #[test]
+    fn test_check_axis() {
+        let mut axis = AIAxis { name: "Test".to_string(), curve: AICurveType::Quadratic, input: AIInputType::MyStockpileOre, foo: 100.0, m: -1.0, k: 3.0, b: 1.0, c: 0.0, invert: false, range: None };
+        assert!(check_axis(&axis).is_empty());
+
+        axis.foo = 0.0;
+        assert_eq!(check_axis(&axis).len(), 1);
+
+        axis.foo = 1.0;
+        assert!(check_curve(&axis).is_empty());
+
+        axis.k = 0.5;
+        axis.c = 0.5;
+        assert_eq!(check_curve(&axis).len(), 1);
+
+        axis.c = 0.0;
+        assert!(check_curve(&axis).is_empty());
+
+        axis.k = 2.0;
+        axis.range = Some((0.5, 0.1));
+        assert_eq!(check_curve(&axis).len(), 1);
+
+        axis.range = None;
+
+        axis.curve = AICurveType::Logit;
+        axis.k = 0.0;
+        assert_eq!(check_curve(&axis).len(), 1);
     }
 }
\ No newline at end of file
@@ -1,4 +1,7 @@
-use serde::{ Serialize, Deserialize, };
+use serde::{ Serialize, Deserialize, de::DeserializeOwned, };
+use ron::de::from_str;
+use std::fs::read_to_string;
+use std::path::{ Path, PathBuf };
 
 #[derive(Serialize, Deserialize)]
 pub struct AssetWorkplaceData {
@@ -6,9 +9,132 @@ pub struct AssetWorkplaceData {
     pub inputs: Vec<u8>,
     pub outs: Vec<u8>,
     pub duration: u32,
+    pub terrain: Vec<u8>,
+    pub size: (u32, u32),
+    pub entrance: (u32, u32),
+    pub cost: Vec<u16>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct AssetItemData {
     pub name: String,
-}
\ No newline at end of file
+}
+
+#[derive(Default)]
+pub struct AssetDefPaths {
+    pub axis: Vec<PathBuf>,
+    pub workplace: Vec<PathBuf>,
+    pub item: Vec<PathBuf>,
+    pub action: Vec<PathBuf>,
+    pub archetype: Vec<PathBuf>,
+}
+
+pub fn get_def_paths(root: &Path) -> AssetDefPaths {
+    let workplace_paths: Vec<PathBuf> = [
+        "Mine",    // 0
+        "Furnace", // 1
+        "Smithy",  // 2
+        "Market",  // 3
+    ].iter().map(|name| root.join("def").join("workplace").join(format!("{}.ron", name))).collect();
+
+    let item_paths: Vec<PathBuf> = [
+        "Amethyst", // 0
+        "Ore",      // 1
+        "Ingot",    // 2
+        "Tools",    // 3
+    ].iter().map(|name| root.join("def").join("item").join(format!("{}.ron", name))).collect();
+
+    let axis_paths: Vec<PathBuf> = [
+        "DistanceFromMe", // 0
+        "OreEmpty",       // 1
+        "IngotEmpty",     // 2
+        "ToolsEmpty",     // 3
+        "OreFull",        // 4
+        "IngotFull",      // 5
+        "ToolsFull",      // 6
+        "OrePriceBuy",    // 7
+        "IngotPriceBuy",  // 8
+        "ToolsPriceBuy",  // 9
+        "OrePriceSell",   // 10
+        "IngotPriceSell", // 11
+        "ToolsPriceSell", // 12
+        "CanBuyOre",      // 13
+        "CanBuyIngot",    // 14
+        "CanBuyTools",    // 15
+        "CanSellOre",     // 16
+        "CanSellIngot",   // 17
+        "CanSellTools",   // 18
+        "TravelTime",     // 19
+        "CrowdAtTarget",  // 20
+    ].iter().map(|name| root.join("def").join("axis").join(format!("{}.ron", name))).collect();
+
+    let action_paths: Vec<PathBuf> = [
+        "Idle",          // 0
+        "WorkAtMine",    // 1
+        "WorkAtFurnace", // 2
+        "WorkAtSmithy",  // 3
+        "BuyOre",        // 4
+        "BuyIngot",      // 5
+        "BuyTools",      // 6
+        "SellOre",       // 7
+        "SellIngot",     // 8
+        "SellTools",     // 9
+    ].iter().map(|name| root.join("def").join("action").join(format!("{}.ron", name))).collect();
+
+    let archetype_paths: Vec<PathBuf> = [
+        "Default", // 0
+        "Greedy",  // 1
+        "Trader",  // 2
+        "Planner", // 3
+    ].iter().map(|name| root.join("def").join("archetype").join(format!("{}.ron", name))).collect();
+
+    return AssetDefPaths { axis: axis_paths, workplace: workplace_paths, item: item_paths, action: action_paths, archetype: archetype_paths };
+}
+
+pub fn load_def<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let text = read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    return from_str::<T>(&text).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+pub fn load_defs<T: DeserializeOwned, F: Fn(&T) -> Vec<String>>(paths: &[PathBuf], check: F) -> Result<Vec<T>, Vec<String>> {
+    let mut defs = Vec::new();
+    let mut errors = Vec::new();
+
+    for path in paths.iter() {
+        match load_def::<T>(path) {
+            Ok(def) => {
+                errors.extend(check(&def));
+                defs.push(def);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if errors.is_empty() {
+        return Ok(defs);
+    } else {
+        return Err(errors);
+    }
+}
+
+pub fn check_workplace(workplace: &AssetWorkplaceData, num_item: usize) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if workplace.inputs.len() != num_item {
+        errors.push(format!("{}: inputs has {} entries, expected {}", workplace.name, workplace.inputs.len(), num_item));
+    }
+    if workplace.outs.len() != num_item {
+        errors.push(format!("{}: outs has {} entries, expected {}", workplace.name, workplace.outs.len(), num_item));
+    }
+    if workplace.cost.len() != num_item {
+        errors.push(format!("{}: cost has {} entries, expected {}", workplace.name, workplace.cost.len(), num_item));
+    }
+    if workplace.size.0 == 0 || workplace.size.1 == 0 {
+        errors.push(format!("{}: size {:?} is empty", workplace.name, workplace.size));
+    }
+    if workplace.entrance.0 >= workplace.size.0 || workplace.entrance.1 >= workplace.size.1 {
+        errors.push(format!("{}: entrance {:?} is outside size {:?}", workplace.name, workplace.entrance, workplace.size));
+    }
+
+    return errors;
+}
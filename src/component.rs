@@ -1,14 +1,16 @@
 use crate::NUM_ITEM;
+use crate::ai::{ AIDecision, AIMemory };
 
 use amethyst::{
     core::math::{ Point2, Point3, Vector3 },
     ecs::{Component, DenseVecStorage, Entity },
 };
 
-use std::collections::HashMap;
+use std::collections::{ HashMap, VecDeque };
 
 pub struct ComponentWorkplace {
     pub variant: u8,
+    pub entrance: Option<Point3<u32>>,
 }
 impl Component for ComponentWorkplace {
     type Storage = DenseVecStorage<Self>;
@@ -34,14 +36,42 @@ impl Component for ComponentPrice {
 
 pub struct ComponentAgent {
     pub actions: [u8; 23],
+    pub archetype: u8,
     pub current: u8,
     pub target: Option<Entity>,
     pub fresh: bool,
+    pub timer: u32,
+    pub aborted: Option<u8>,
+    pub plan: VecDeque<u8>,
 }
 impl Component for ComponentAgent {
     type Storage = DenseVecStorage<Self>;
 }
 
+#[derive(Default)]
+pub struct ComponentActionState {
+    pub delay: Option<u32>,
+}
+impl Component for ComponentActionState {
+    type Storage = DenseVecStorage<Self>;
+}
+
+#[derive(Default)]
+pub struct ComponentMemory {
+    pub markets: HashMap<Entity, AIMemory>,
+}
+impl Component for ComponentMemory {
+    type Storage = DenseVecStorage<Self>;
+}
+
+pub struct ComponentDecisionLog {
+    pub decisions: VecDeque<AIDecision>,
+    pub limit: usize,
+}
+impl Component for ComponentDecisionLog {
+    type Storage = DenseVecStorage<Self>;
+}
+
 pub struct ComponentMovement {
     pub targets: Vec<Point3<u32>>,
     pub velocity: Vector3<f32>,
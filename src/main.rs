@@ -6,19 +6,21 @@ mod asset;
 mod ai;
 mod map;
 mod ui;
+mod validate;
 
 use crate::misc::*;
 use crate::state::*;
 use crate::system::*;
 use crate::ui::*;
+use crate::validate::*;
 
 extern crate rand;
 extern crate ron;
 extern crate voronoi;
 extern crate noise;
+extern crate image;
 
 const NUM_ITEM: usize = 4;
-const MAP_SIZE: u32 = 200;
 
 use amethyst::{
     core::{
@@ -47,6 +49,22 @@ fn main() -> amethyst::Result<()> {
 
     let app_root = application_root_dir()?;
 
+    if std::env::args().nth(1).map(|arg| arg == "validate").unwrap_or(false) {
+        let errors = validate(&app_root.join("asset"));
+
+        for error in errors.iter() {
+            println!("{}", error);
+        }
+
+        if errors.is_empty() {
+            println!("All definitions are valid");
+            return Ok(());
+        } else {
+            println!("{} errors", errors.len());
+            std::process::exit(1);
+        }
+    }
+
     let asset_dir = app_root.join("asset");
     let config_dir = app_root.join("config");
     let display_config_path = config_dir.join("display.ron");
@@ -54,16 +72,26 @@ fn main() -> amethyst::Result<()> {
 
     let game_data = GameDataBuilder::default()
         .with(SystemCameraMovement::default(), "Camera Movement System", &[])
-        .with(SystemMovement::default(), "Character Movement System", &[])
+        .with(SystemChunk::default(), "Chunk System", &["Camera Movement System"])
+        .with(SystemMovement::default(), "Character Movement System", &["Chunk System"])
         .with(SystemMapMode::default(), "Mapmode System", &[])
         .with(SystemTime::default(), "Time System", &[])
-        .with(SystemAI::default(), "AI System", &[])
+        .with(SystemSpatial::default(), "Spatial System", &[])
+        .with(SystemTravel::default(), "Travel System", &["Chunk System"])
+        .with(SystemMemory::default(), "Memory System", &["Spatial System"])
+        .with(SystemAI::default(), "AI System", &["Spatial System", "Travel System", "Memory System"])
         .with(SystemPrice::default(), "Price System", &[])
+        .with(SystemReload::default(), "Reload System", &[])
         .with_bundle(
             InputBundle::<StringBindings>::new()
                 .with_bindings_from_file(input_config_path)?,
         )?
         .with_system_desc(SystemMovementPlayerDesc::default(), "Player Movement System", &["input_system"])
+        .with_system_desc(SystemMapExportDesc::default(), "Map Export System", &["input_system"])
+        .with_system_desc(SystemBuildDesc::default(), "Build System", &["input_system", "Chunk System"])
+        .with_system_desc(SystemSelectionDesc::default(), "Selection System", &["input_system", "Build System"])
+        .with_system_desc(SystemTradeDesc::default(), "Trade System", &["input_system", "Selection System"])
+        .with_system_desc(SystemCurveEditorDesc::default(), "Curve Editor System", &["input_system"])
         .with_bundle(TransformBundle::new())?
         .with_bundle(
             RenderingBundle::<DefaultBackend>::new()
@@ -72,7 +100,7 @@ fn main() -> amethyst::Result<()> {
                         .with_clear([0.0, 0.0, 0.0, 1.0]),
                 )
                 .with_plugin(RenderFlat2D::default())
-                .with_plugin(RenderTiles2D::<MiscTile, MortonEncoder2D, MiscTileBounds>::default())
+                .with_plugin(RenderTiles2D::<MiscTileLayer, MortonEncoder2D, MiscTileBounds>::default())
                 .with_plugin(RenderUi::default())
         )?
         .with_bundle(UiBundle::<StringBindings, CustomUi>::new())?
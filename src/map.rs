@@ -1,275 +1,881 @@
 use crate::misc::*;
 use crate::component::*;
-use crate::MAP_SIZE;
-
-use amethyst::{
-    core::math::Point3,
-    tiles::{
-        TileMap, MapStorage, MortonEncoder2D, Map,
-    },
-};
-use rand::Rng;
+use crate::asset::AssetWorkplaceData;
+
+use amethyst::{ core::math::Point3, ecs::Entity };
+use rand::{ Rng, SeedableRng, rngs::StdRng };
 use voronoi::{voronoi, Point, lloyd_relaxation, DCEL, make_polygons};
 use noise::{ NoiseFn, Perlin, Seedable };
+use serde::{ Serialize, Deserialize };
+use ron::{ de::from_str, ser::{ to_string_pretty, PrettyConfig } };
+use image::{ Rgb, RgbImage };
 use std::cmp::Ordering;
+use std::collections::{ HashMap, HashSet };
+use std::fs::{ read_to_string, read_dir, remove_file, write };
+use std::path::{ Path, PathBuf };
+
+pub const TERRAIN_COLORS: [[u8; 3]; 10] = [
+    [200, 200, 0],
+    [0, 200, 200],
+    [0, 0, 200],
+    [0, 100, 0],
+    [0, 200, 0],
+    [200, 0, 0],
+    [0, 50, 0],
+    [140, 120, 70],
+    [120, 120, 120],
+    [60, 120, 220],
+];
+
+pub const RESOURCE_COLORS: [[u8; 3]; 6] = [
+    [0, 0, 0],
+    [150, 80, 200],
+    [230, 190, 40],
+    [170, 170, 190],
+    [110, 100, 90],
+    [30, 30, 30],
+];
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum MapGenSide {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
 
-type Segment = [Point; 2];
+#[derive(Clone, Serialize, Deserialize)]
+pub enum MapGenSea {
+    Sides(Vec<MapGenSide>),
+    Island,
+}
 
-pub fn gen_map(tiles: &mut TileMap<MiscTile, MortonEncoder2D>) {
-    let mut rng = rand::thread_rng();
-    let mut vor_pts = Vec::<Point>::new();
-    let map_size = MAP_SIZE as f64;
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MapGenConfig {
+    pub width: u32,
+    pub height: u32,
+    pub seed: Option<u64>,
+    pub points: u32,
+    pub relaxation: u32,
+    pub sea: MapGenSea,
+    pub depth_sea: f64,
+    pub depth_coast: u32,
+    pub depth_beach: u32,
+    pub resource_scale: f64,
+    pub resource_weights: Vec<(f64, f64)>,
+    pub resource_quantity: u16,
+    pub elevation_scale: f64,
+    pub elevation_octaves: u32,
+    pub elevation_noise: f64,
+    pub moisture_scale: f64,
+    pub hills: f64,
+    pub mountains: f64,
+    pub forest: f64,
+    pub rivers: u32,
+    pub chunk: u32,
+    pub keep: u32,
+    pub chunks: String,
+    pub import: Option<String>,
+    pub export: String,
+}
+impl Default for MapGenConfig {
+    fn default() -> Self {
+        MapGenConfig {
+            width: 200,
+            height: 200,
+            seed: None,
+            points: 300,
+            relaxation: 2,
+            sea: MapGenSea::Sides(vec![MapGenSide::Right]),
+            depth_sea: 0.1,
+            depth_coast: 2,
+            depth_beach: 2,
+            resource_scale: 12.3456789,
+            resource_weights: vec![(0.0, 16.0), (0.2, 6.0), (0.4, 4.0), (0.6, 4.0), (0.8, 4.0), (1.0, 3.5)],
+            resource_quantity: 500,
+            elevation_scale: 48.0,
+            elevation_octaves: 4,
+            elevation_noise: 0.5,
+            moisture_scale: 32.0,
+            hills: 0.6,
+            mountains: 0.8,
+            forest: 0.55,
+            rivers: 6,
+            chunk: 64,
+            keep: 1,
+            chunks: "chunks".to_string(),
+            import: None,
+            export: "map_export".to_string(),
+        }
+    }
+}
 
-    for _ in 0..(100 + MAP_SIZE) {
-        vor_pts.push(Point::new(rng.gen::<f64>() * map_size, rng.gen::<f64>() * map_size))
+fn is_sea(point: &Point, config: &MapGenConfig) -> bool {
+    let x = point.x.into_inner() / config.width as f64;
+    let y = point.y.into_inner() / config.height as f64;
+
+    match &config.sea {
+        MapGenSea::Sides(sides) => {
+            sides.iter().any(|side| match side {
+                MapGenSide::Left => x < config.depth_sea,
+                MapGenSide::Right => x > 1.0 - config.depth_sea,
+                MapGenSide::Top => y < config.depth_sea,
+                MapGenSide::Bottom => y > 1.0 - config.depth_sea,
+            })
+        }
+        MapGenSea::Island => {
+            (x * 2.0 - 1.0).powf(2.0) + (y * 2.0 - 1.0).powf(2.0) > (1.0 - config.depth_sea).powf(2.0)
+        }
     }
+}
 
-    let vor_pts = lloyd_relaxation(vor_pts, map_size);
-    let vor_pts = lloyd_relaxation(vor_pts, map_size);
+pub struct MapGen {
+    pub config: MapGenConfig,
+    polys: Vec<Vec<Point>>,
+    buckets: HashMap<(i64, i64), Vec<usize>>,
+    bucket: f64,
+    seas: HashSet<usize>,
+    coasts: HashSet<usize>,
+    beaches: HashSet<usize>,
+    depths: Vec<u32>,
+    depth_max: f64,
+    perlin: Perlin,
+    perlin_elevation: Perlin,
+    perlin_moisture: Perlin,
+    rivers: HashSet<(u32, u32)>,
+}
+impl MapGen {
+    pub fn new(config: &MapGenConfig) -> Self {
+        let mut rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut vor_pts = Vec::<Point>::new();
+        let map_width = config.width as f64;
+        let map_height = config.height as f64;
+        let map_size = map_width.max(map_height);
+
+        for _ in 0..config.points {
+            vor_pts.push(Point::new(rng.gen::<f64>() * map_width, rng.gen::<f64>() * map_height))
+        }
 
-    let vor_diagram = voronoi(vor_pts, map_size);
-    let vor_polys = make_polygons(&vor_diagram);
+        for _ in 0..config.relaxation {
+            vor_pts = lloyd_relaxation(vor_pts, map_size);
+        }
 
-    let mut vor_seas = Vec::<Point>::new();
-    let mut vor_coasts = Vec::<Point>::new();
-    let mut vor_beaches = Vec::<Point>::new();
-    let mut vor_seas_poly = Vec::<Vec<Point>>::new();
-    let mut vor_coasts_poly = Vec::<Vec<Point>>::new();
-    let mut vor_beaches_poly = Vec::<Vec<Point>>::new();
+        let vor_diagram = voronoi(vor_pts, map_size);
+        let vor_polys = make_polygons(&vor_diagram);
 
-    let mut depth_coast = 2;
-    let mut depth_beach = 2;
+        let mut corners: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
 
-    for poly in vor_polys.iter() {
-        for point in poly.iter() {
-            if point.x.into_inner() > map_size * 0.9 {
-                vor_seas_poly.push(poly.clone());
+        for (i, poly) in vor_polys.iter().enumerate() {
+            for point in poly.iter() {
+                corners.entry((point.x.into_inner().to_bits(), point.y.into_inner().to_bits())).or_insert_with(Vec::new).push(i);
+            }
+        }
 
-                for point in poly.iter() {
-                    if !vor_seas.contains(point) {
-                        vor_seas.push(point.clone());
+        let mut neighbors = vec![HashSet::<usize>::new(); vor_polys.len()];
+
+        for cells in corners.values() {
+            for a in cells.iter() {
+                for b in cells.iter() {
+                    if a != b {
+                        neighbors[*a].insert(*b);
                     }
                 }
-
-                break;
             }
         }
-    }
 
-    for poly in vor_polys.iter() {
-        if !vor_seas_poly.contains(poly) {
-            for point in poly.iter() {
-                if vor_seas.contains(point) {
-                    vor_coasts_poly.push(poly.clone());
+        let vor_seas: HashSet<usize> = vor_polys
+            .iter()
+            .enumerate()
+            .filter(|(_, poly)| poly.iter().any(|point| is_sea(point, config)))
+            .map(|(i, _)| i)
+            .collect();
+        let vor_coasts = get_band(&vor_seas, &neighbors, config.depth_coast, &vor_seas);
+        let vor_beaches = get_band(&vor_coasts, &neighbors, config.depth_beach, &vor_seas.union(&vor_coasts).cloned().collect());
+
+        let bucket = (map_width * map_height / config.points.max(1) as f64).sqrt().max(1.0);
+        let mut buckets: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+
+        for (i, poly) in vor_polys.iter().enumerate() {
+            if poly.len() < 3 {
+                continue;
+            }
 
-                    for point in poly.iter() {
-                        if !vor_coasts.contains(point) {
-                            vor_coasts.push(point.clone());
-                        }
-                    }
+            let xs = poly.iter().map(|point| point.x.into_inner());
+            let ys = poly.iter().map(|point| point.y.into_inner());
+            let (x0, x1) = (xs.clone().fold(std::f64::INFINITY, f64::min), xs.fold(std::f64::NEG_INFINITY, f64::max));
+            let (y0, y1) = (ys.clone().fold(std::f64::INFINITY, f64::min), ys.fold(std::f64::NEG_INFINITY, f64::max));
 
-                    break;
+            for by in (y0 / bucket).floor() as i64..=(y1 / bucket).floor() as i64 {
+                for bx in (x0 / bucket).floor() as i64..=(x1 / bucket).floor() as i64 {
+                    buckets.entry((bx, by)).or_insert_with(Vec::new).push(i);
                 }
             }
         }
+
+        let mut depths = vec![0u32; vor_polys.len()];
+        let mut frontier = vor_seas.clone();
+        let mut seen = vor_seas.clone();
+        let mut depth = 0;
+
+        while !frontier.is_empty() {
+            depth += 1;
+
+            let next: HashSet<usize> = frontier
+                .iter()
+                .flat_map(|cell| neighbors[*cell].iter())
+                .filter(|cell| !seen.contains(*cell))
+                .cloned()
+                .collect();
+
+            for cell in next.iter() {
+                depths[*cell] = depth;
+            }
+
+            seen.extend(next.iter().cloned());
+            frontier = next;
+        }
+
+        let mut map_gen = MapGen {
+            config: config.clone(),
+            polys: vor_polys,
+            buckets,
+            bucket,
+            seas: vor_seas,
+            coasts: vor_coasts,
+            beaches: vor_beaches,
+            depth_max: depths.iter().cloned().max().unwrap_or(0).max(1) as f64,
+            depths,
+            perlin: Perlin::new().set_seed(rng.gen::<u32>()),
+            perlin_elevation: Perlin::new().set_seed(rng.gen::<u32>()),
+            perlin_moisture: Perlin::new().set_seed(rng.gen::<u32>()),
+            rivers: HashSet::new(),
+        };
+
+        map_gen.rivers = map_gen.get_rivers(&mut rng);
+
+        map_gen
     }
 
-    depth_coast -= 1;
+    fn get_cell(&self, x: u32, y: u32) -> usize {
+        let key = ((x as f64 / self.bucket).floor() as i64, (y as f64 / self.bucket).floor() as i64);
 
-    while depth_coast > 0 {
-        depth_coast -= 1;
+        self.buckets
+            .get(&key)
+            .and_then(|cells| cells.iter().rev().find(|cell| is_inside(&self.polys[**cell], x as f64, y as f64)))
+            .cloned()
+            .unwrap_or(usize::max_value())
+    }
 
-        let mut t = Vec::<Point>::new();
-        let mut tt = Vec::<Vec<Point>>::new();
+    fn get_cells(&self, min: (u32, u32), max: (u32, u32)) -> Vec<usize> {
+        let width = (max.0 - min.0) as usize;
+        let mut cells = vec![usize::max_value(); width * (max.1 - min.1) as usize];
 
-        for poly in vor_polys.iter() {
-            if !vor_seas_poly.contains(poly) && !vor_coasts_poly.contains(poly) {
-                for point in poly.iter() {
-                    if vor_coasts.contains(point) {
-                        tt.push(poly.clone());
-    
-                        for point in poly.iter() {
-                            if !vor_coasts.contains(point) && !t.contains(point) {
-                                t.push(point.clone());
-                            }
-                        }
-    
-                        break;
-                    }
+        if cells.is_empty() {
+            return cells;
+        }
+
+        let mut candidates = HashSet::new();
+
+        for by in (min.1 as f64 / self.bucket).floor() as i64..=((max.1 - 1) as f64 / self.bucket).floor() as i64 {
+            for bx in (min.0 as f64 / self.bucket).floor() as i64..=((max.0 - 1) as f64 / self.bucket).floor() as i64 {
+                if let Some(bucket) = self.buckets.get(&(bx, by)) {
+                    candidates.extend(bucket.iter().cloned());
                 }
             }
         }
 
-        vor_coasts.append(&mut t);
-        vor_coasts_poly.append(&mut tt);
+        let mut candidates: Vec<usize> = candidates.into_iter().collect();
+        candidates.sort();
+
+        for cell in candidates {
+            for (x, y) in get_raster(&self.polys[cell], min, max) {
+                cells[(y - min.1) as usize * width + (x - min.0) as usize] = cell;
+            }
+        }
+
+        cells
     }
 
-    for poly in vor_polys.iter() {
-        if !vor_seas_poly.contains(poly) && !vor_coasts_poly.contains(poly) {
-            for point in poly.iter() {
-                if vor_coasts.contains(point) {
-                    vor_beaches_poly.push(poly.clone());
+    fn get_base(&self, cell: usize, x: u32, y: u32) -> (u8, f64) {
+        if self.seas.contains(&cell) {
+            (TERRAIN_SEA, 0.0)
+        } else if self.coasts.contains(&cell) {
+            (TERRAIN_COAST, 0.05)
+        } else if self.beaches.contains(&cell) {
+            (TERRAIN_BEACH, 0.1)
+        } else {
+            let config = &self.config;
+            let inland = self.depths.get(cell).map_or(1.0, |depth| *depth as f64 / self.depth_max);
+            let noise = get_fbm(&self.perlin_elevation, x as f64, y as f64, config.elevation_scale, config.elevation_octaves);
+
+            (TERRAIN_PLAINS, 0.15 + 0.85 * (inland * (1.0 - config.elevation_noise) + noise * config.elevation_noise))
+        }
+    }
 
-                    for point in poly.iter() {
-                        if !vor_beaches.contains(point) {
-                            vor_beaches.push(point.clone());
-                        }
-                    }
+    fn get_rivers(&self, rng: &mut StdRng) -> HashSet<(u32, u32)> {
+        let config = &self.config;
+        let (width, height) = (config.width as i64, config.height as i64);
+        let mut rivers = HashSet::new();
+        let mut sources = Vec::new();
+
+        for _ in 0..config.rivers * 100 {
+            if sources.len() >= config.rivers as usize || config.width == 0 || config.height == 0 {
+                break;
+            }
 
+            let source = (rng.gen_range(0, config.width), rng.gen_range(0, config.height));
+            let (terrain, elevation) = self.get_base(self.get_cell(source.0, source.1), source.0, source.1);
+
+            if terrain == TERRAIN_PLAINS && elevation >= config.hills {
+                sources.push(source);
+            }
+        }
+
+        for source in sources {
+            let mut current = source;
+            let mut visited = HashSet::new();
+
+            while visited.len() < (width + height) as usize * 4 {
+                let terrain = self.get_base(self.get_cell(current.0, current.1), current.0, current.1).0;
+
+                if terrain == TERRAIN_SEA || terrain == TERRAIN_COAST {
                     break;
                 }
+                if rivers.contains(&current) && !visited.is_empty() {
+                    break;
+                }
+
+                rivers.insert(current);
+                visited.insert(current);
+
+                let (x, y) = (current.0 as i64, current.1 as i64);
+
+                let next = [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (1, 1), (-1, 1)]
+                    .iter()
+                    .filter(|(dx, dy)| x + dx >= 0 && y + dy >= 0 && x + dx < width && y + dy < height)
+                    .map(|(dx, dy)| ((x + dx) as u32, (y + dy) as u32))
+                    .filter(|point| !visited.contains(point))
+                    .map(|point| (point, self.get_base(self.get_cell(point.0, point.1), point.0, point.1).1))
+                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+
+                match next {
+                    Some((next, _)) => current = next,
+                    None => break,
+                }
             }
         }
+
+        rivers
     }
 
-    depth_beach -= 1;
+    pub fn gen_chunk(&self, chunk: (u32, u32), size: u32) -> Vec<MiscTile> {
+        let config = &self.config;
+        let mut tiles = Vec::with_capacity((size * size) as usize);
+        let min = ((chunk.0 * size).min(config.width), (chunk.1 * size).min(config.height));
+        let max = ((chunk.0 * size + size).min(config.width), (chunk.1 * size + size).min(config.height));
+        let cells = self.get_cells(min, max);
+
+        for dy in 0..size {
+            for dx in 0..size {
+                let (x, y) = (chunk.0 * size + dx, chunk.1 * size + dy);
 
-    while depth_beach > 0 {
-        depth_beach -= 1;
+                if x >= config.width || y >= config.height {
+                    tiles.push(MiscTile { terrain: TERRAIN_SEA, ..MiscTile::default() });
+                    continue;
+                }
+
+                let cell = cells[(y - min.1) as usize * (max.0 - min.0) as usize + (x - min.0) as usize];
+                let (mut terrain, elevation) = self.get_base(cell, x, y);
+
+                if terrain != TERRAIN_SEA && terrain != TERRAIN_COAST && self.rivers.contains(&(x, y)) {
+                    terrain = TERRAIN_RIVER;
+                }
 
-        let mut t = Vec::<Point>::new();
-        let mut tt = Vec::<Vec<Point>>::new();
+                if terrain == TERRAIN_PLAINS {
+                    let mut moisture = get_fbm(&self.perlin_moisture, x as f64, y as f64, config.moisture_scale, config.elevation_octaves);
 
-        for poly in vor_polys.iter() {
-            if !vor_seas_poly.contains(poly) && !vor_coasts_poly.contains(poly) && !vor_beaches_poly.contains(poly) {
-                for point in poly.iter() {
-                    if vor_beaches.contains(point) {
-                        tt.push(poly.clone());
-    
-                        for point in poly.iter() {
-                            if !vor_beaches.contains(point) && !t.contains(point) {
-                                t.push(point.clone());
+                    for ry in y.saturating_sub(2)..=y + 2 {
+                        for rx in x.saturating_sub(2)..=x + 2 {
+                            if self.rivers.contains(&(rx, ry)) {
+                                moisture += 0.1;
                             }
                         }
-    
-                        break;
+                    }
+
+                    if elevation >= config.mountains {
+                        terrain = TERRAIN_MOUNTAINS;
+                    } else if elevation >= config.hills {
+                        terrain = TERRAIN_HILLS;
+                    } else if moisture.min(1.0) >= config.forest {
+                        terrain = TERRAIN_FOREST;
                     }
                 }
+
+                let resource = config.resource_weights
+                    .iter()
+                    .map(|(a, b)| (self.perlin.get([x as f64 / config.resource_scale, y as f64 / config.resource_scale, *a]) + 0.8660254) * b)
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+                    .map(|(i, _)| i)
+                    .unwrap() as u8;
+
+                tiles.push(MiscTile {
+                    terrain,
+                    resource,
+                    quantity: if resource != 0 { config.resource_quantity } else { 0 },
+                    ..MiscTile::default()
+                });
             }
         }
 
-        vor_beaches.append(&mut t);
-        vor_beaches_poly.append(&mut tt);
+        tiles
     }
+}
 
-    let perlin = Perlin::new().set_seed(rng.gen::<u32>());
-    let resources = [(0.0, 16.0), (0.2, 6.0), (0.4, 4.0), (0.6, 4.0), (0.8, 4.0), (1.0, 3.5)];
-
-    for y in 0..MAP_SIZE as usize {
-        for x in 0..MAP_SIZE as usize {
-            let tile = tiles.get_mut(&Point3::new(x as u32, y as u32, 0)).unwrap();
+fn get_fbm(perlin: &Perlin, x: f64, y: f64, scale: f64, octaves: u32) -> f64 {
+    let mut out = 0.0;
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0 / scale;
+
+    for _ in 0..octaves.max(1) {
+        out += perlin.get([x * frequency, y * frequency]) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
 
-            tile.terrain = 3;
+    ((out / total + 1.0) / 2.0).max(0.0).min(1.0)
+}
 
-            for poly in vor_beaches_poly.iter() {
-                let mut segments = Vec::<Segment>::new();
+#[derive(Serialize, Deserialize)]
+pub struct MapGrid {
+    pub width: u32,
+    pub height: u32,
+    pub terrain: Vec<u8>,
+    pub resource: Vec<u8>,
+    #[serde(default)]
+    pub quantity: Vec<u16>,
+}
 
-                for (i, point) in poly.iter().enumerate() {
-                    if i + 1 < poly.len() {
-                        segments.push([point.clone(), poly[i + 1].clone()]);
-                    } else {
-                        segments.push([point.clone(), poly[0].clone()]);
+pub fn get_grid(chunks: &MiscChunks, map_gen: Option<&MapGen>, dir: &Path) -> Result<MapGrid, String> {
+    let (columns, rows) = chunks.dimensions();
+    let size = chunks.size;
+
+    let mut grid = MapGrid {
+        width: chunks.width,
+        height: chunks.height,
+        terrain: vec![TERRAIN_SEA; (chunks.width * chunks.height) as usize],
+        resource: vec![0; (chunks.width * chunks.height) as usize],
+        quantity: vec![0; (chunks.width * chunks.height) as usize],
+    };
+
+    for cy in 0..rows {
+        for cx in 0..columns {
+            let tiles = match chunks.chunks.get(&(cx, cy)) {
+                Some(tiles) => tiles.clone(),
+                None => match load_chunk((cx, cy), dir) {
+                    Ok(Some(tiles)) => tiles,
+                    Ok(None) => match map_gen {
+                        Some(map_gen) => map_gen.gen_chunk((cx, cy), size),
+                        None => return Err(format!("chunk {},{} is missing", cx, cy)),
+                    },
+                    Err(e) => return Err(e),
+                },
+            };
+
+            for dy in 0..size {
+                for dx in 0..size {
+                    let (x, y) = (cx * size + dx, cy * size + dy);
+
+                    if x < grid.width && y < grid.height {
+                        let tile = &tiles[(dy * size + dx) as usize];
+                        let i = (y * grid.width + x) as usize;
+
+                        grid.terrain[i] = tile.terrain;
+                        grid.resource[i] = tile.resource;
+                        grid.quantity[i] = tile.quantity;
                     }
                 }
+            }
+        }
+    }
 
-                let check = [Point::new(x as f64, y as f64), Point::new(map_size + 10.0, y as f64)];
-                let mut count = 0;
+    Ok(grid)
+}
 
-                for segment in segments.iter() {
-                    match segment_intersection(check, *segment) {
-                        Some(_) => count += 1,
-                        None => (),
-                    };
-                }
+pub fn set_grid(chunks: &mut MiscChunks, grid: &MapGrid, config: &MapGenConfig) {
+    let (columns, rows) = chunks.dimensions();
+    let size = chunks.size;
+
+    for cy in 0..rows {
+        for cx in 0..columns {
+            chunks.chunks.insert((cx, cy), vec![MiscTile { terrain: TERRAIN_SEA, ..MiscTile::default() }; (size * size) as usize]);
+        }
+    }
 
-                if count % 2 == 1 {
-                    tile.terrain = 0;
+    for y in 0..chunks.height.min(grid.height) {
+        for x in 0..chunks.width.min(grid.width) {
+            let tile = chunks.get_mut(&Point3::new(x, y, 0)).unwrap();
+            let i = (y * grid.width + x) as usize;
+
+            tile.terrain = grid.terrain[i];
+            tile.resource = grid.resource[i];
+            tile.quantity = match grid.quantity.get(i) {
+                Some(quantity) => *quantity,
+                None if tile.resource != 0 => config.resource_quantity,
+                None => 0,
+            };
+        }
+    }
+}
 
-                    break;
-                }
+pub fn get_chunk_dir(root: &Path, config: &MapGenConfig) -> PathBuf {
+    let name = Path::new(&config.chunks).file_name().map_or("chunks".into(), |name| name.to_os_string());
+
+    root.join("cache").join(name)
+}
+
+fn get_chunk_path(chunk: (u32, u32), dir: &Path) -> PathBuf {
+    dir.join(format!("{}_{}.ron", chunk.0, chunk.1))
+}
+
+fn is_chunk_file(name: &str) -> bool {
+    let mut parts = name.trim_end_matches(".ron").split('_');
+
+    name.ends_with(".ron")
+        && parts.next().map_or(false, |x| x.parse::<u32>().is_ok())
+        && parts.next().map_or(false, |y| y.parse::<u32>().is_ok())
+        && parts.next().is_none()
+}
+
+pub fn clear_chunks(dir: &Path) -> Result<(), String> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+
+        if path.is_file() && path.file_name().and_then(|name| name.to_str()).map_or(false, is_chunk_file) {
+            remove_file(&path).map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn save_chunk(tiles: &Vec<MiscTile>, chunk: (u32, u32), dir: &Path) -> Result<(), String> {
+    write(get_chunk_path(chunk, dir), to_string_pretty(tiles, PrettyConfig::default()).map_err(|e| e.to_string())?).map_err(|e| e.to_string())
+}
+
+pub fn load_chunk(chunk: (u32, u32), dir: &Path) -> Result<Option<Vec<MiscTile>>, String> {
+    let path = get_chunk_path(chunk, dir);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(from_str::<Vec<MiscTile>>(&read_to_string(&path).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?))
+}
+
+// Chunk files don't keep buildings, so tiles that are loaded or generated again get them
+// back from the workplace footprints overlapping the chunk.
+pub fn set_buildings(chunk: (u32, u32), tiles: &mut Vec<MiscTile>, chunks: &MiscChunks, buildings: &[(Entity, Point3<u32>, &AssetWorkplaceData)]) {
+    for (entity, origin, workplace_data) in buildings.iter() {
+        let entrance = get_entrance(origin, workplace_data);
+
+        for point in get_footprint(origin, workplace_data).into_iter().filter(|point| chunks.get_chunk(point) == chunk) {
+            if let Some(tile) = tiles.get_mut(chunks.get_index(&point)) {
+                tile.building = Some(*entity);
+                tile.entrance = point == entrance;
             }
-            for poly in vor_coasts_poly.iter() {
-                let mut segments = Vec::<Segment>::new();
-
-                for (i, point) in poly.iter().enumerate() {
-                    if i + 1 < poly.len() {
-                        segments.push([point.clone(), poly[i + 1].clone()]);
-                    } else {
-                        segments.push([point.clone(), poly[0].clone()]);
-                    }
-                }
+        }
+    }
+}
 
-                let check = [Point::new(x as f64, y as f64), Point::new(map_size + 10.0, y as f64)];
-                let mut count = 0;
+pub fn save_grid(grid: &MapGrid, path: &Path) -> Result<(), String> {
+    let mut terrain = RgbImage::new(grid.width, grid.height);
+    let mut resource = RgbImage::new(grid.width, grid.height);
 
-                for segment in segments.iter() {
-                    match segment_intersection(check, *segment) {
-                        Some(_) => count += 1,
-                        None => (),
-                    };
-                }
+    for y in 0..grid.height {
+        for x in 0..grid.width {
+            let i = (y * grid.width + x) as usize;
 
-                if count % 2 == 1 {
-                    tile.terrain = 1;
+            terrain.put_pixel(x, y, Rgb(TERRAIN_COLORS[grid.terrain[i] as usize % TERRAIN_COLORS.len()]));
+            resource.put_pixel(x, y, Rgb(RESOURCE_COLORS[grid.resource[i] as usize % RESOURCE_COLORS.len()]));
+        }
+    }
 
-                    break;
-                }
+    terrain.save(path.with_extension("png")).map_err(|e| e.to_string())?;
+    resource.save(get_resource_path(path)).map_err(|e| e.to_string())?;
+    write(path.with_extension("ron"), to_string_pretty(grid, PrettyConfig::default()).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+pub fn load_grid(path: &Path) -> Result<MapGrid, String> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("ron") => {
+            let grid = from_str::<MapGrid>(&read_to_string(path).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+
+            if grid.terrain.len() != (grid.width * grid.height) as usize || grid.resource.len() != grid.terrain.len() || (!grid.quantity.is_empty() && grid.quantity.len() != grid.terrain.len()) {
+                return Err(format!("{}: grid size does not match {}x{}", path.display(), grid.width, grid.height));
             }
-            for poly in vor_seas_poly.iter() {
-                let mut segments = Vec::<Segment>::new();
-
-                for (i, point) in poly.iter().enumerate() {
-                    if i + 1 < poly.len() {
-                        segments.push([point.clone(), poly[i + 1].clone()]);
-                    } else {
-                        segments.push([point.clone(), poly[0].clone()]);
-                    }
+
+            Ok(grid)
+        }
+        Some("png") => {
+            let terrain = image::open(path).map_err(|e| e.to_string())?.to_rgb();
+            let resource = match image::open(get_resource_path(path)) {
+                Ok(resource) => Some(resource.to_rgb()),
+                Err(_) => None,
+            };
+
+            let mut grid = MapGrid {
+                width: terrain.width(),
+                height: terrain.height(),
+                terrain: Vec::new(),
+                resource: Vec::new(),
+                quantity: Vec::new(),
+            };
+
+            for y in 0..grid.height {
+                for x in 0..grid.width {
+                    grid.terrain.push(get_class(&TERRAIN_COLORS, &terrain.get_pixel(x, y).0));
+                    grid.resource.push(match &resource {
+                        Some(resource) if x < resource.width() && y < resource.height() => get_class(&RESOURCE_COLORS, &resource.get_pixel(x, y).0),
+                        _ => 0,
+                    });
                 }
+            }
 
-                let check = [Point::new(x as f64, y as f64), Point::new(map_size + 10.0, y as f64)];
-                let mut count = 0;
+            Ok(grid)
+        }
+        _ => Err(format!("{}: expected a .png or .ron map", path.display())),
+    }
+}
 
-                for segment in segments.iter() {
-                    match segment_intersection(check, *segment) {
-                        Some(_) => count += 1,
-                        None => (),
-                    };
-                }
+fn get_resource_path(path: &Path) -> std::path::PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("map");
 
-                if count % 2 == 1 {
-                    tile.terrain = 2;
+    path.with_file_name(format!("{}_resource.png", stem))
+}
 
-                    break;
+fn get_class(colors: &[[u8; 3]], pixel: &[u8; 3]) -> u8 {
+    colors
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, color)| (0..3).map(|i| (color[i] as i32 - pixel[i] as i32).pow(2)).sum::<i32>())
+        .map(|(i, _)| i)
+        .unwrap() as u8
+}
+
+fn get_band(from: &HashSet<usize>, neighbors: &Vec<HashSet<usize>>, depth: u32, taken: &HashSet<usize>) -> HashSet<usize> {
+    let mut band = HashSet::new();
+    let mut frontier = from.clone();
+
+    for _ in 0..depth {
+        let next: HashSet<usize> = frontier
+            .iter()
+            .flat_map(|cell| neighbors[*cell].iter())
+            .filter(|cell| !taken.contains(*cell) && !band.contains(*cell))
+            .cloned()
+            .collect();
+
+        band.extend(next.iter().cloned());
+        frontier = next;
+    }
+
+    band
+}
+
+pub fn get_raster(poly: &Vec<Point>, min: (u32, u32), max: (u32, u32)) -> Vec<(u32, u32)> {
+    let mut out = Vec::new();
+
+    if poly.len() < 3 {
+        return out;
+    }
+
+    let top = poly.iter().map(|point| point.y.into_inner()).fold(std::f64::INFINITY, f64::min).ceil().max(min.1 as f64) as u32;
+    let bottom = poly.iter().map(|point| point.y.into_inner()).fold(std::f64::NEG_INFINITY, f64::max).floor().max(0.0) as u32;
+
+    for y in top..=bottom.min(max.1.saturating_sub(1)) {
+        let yf = y as f64;
+        let mut crossings = Vec::new();
+
+        for (i, p) in poly.iter().enumerate() {
+            let q = &poly[(i + 1) % poly.len()];
+            let (px, py, qx, qy) = (p.x.into_inner(), p.y.into_inner(), q.x.into_inner(), q.y.into_inner());
+
+            if (py <= yf && yf < qy) || (qy <= yf && yf < py) {
+                crossings.push(px + (yf - py) * (qx - px) / (qy - py));
+            }
+        }
+
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        for pair in crossings.chunks(2) {
+            if pair.len() == 2 {
+                let start = pair[0].ceil().max(min.0 as f64) as u32;
+                let end = pair[1].ceil().max(min.0 as f64).min(max.0 as f64) as u32;
+
+                for x in start..end {
+                    out.push((x, y));
                 }
             }
+        }
+    }
 
-            tile.resource = resources
-                .iter()
-                .map(|(a, b)| (perlin.get([x as f64 / 12.3456789, y as f64 / 12.3456789, *a]) + 0.8660254) * b)
-                .enumerate()
-                .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-                .map(|(i, _)| i)
-                .unwrap() as u8;
+    out
+}
+
+fn is_inside(poly: &Vec<Point>, x: f64, y: f64) -> bool {
+    let mut inside = false;
+
+    for (i, p) in poly.iter().enumerate() {
+        let q = &poly[(i + 1) % poly.len()];
+        let (px, py, qx, qy) = (p.x.into_inner(), p.y.into_inner(), q.x.into_inner(), q.y.into_inner());
+
+        if ((py <= y && y < qy) || (qy <= y && y < py)) && x >= px + (y - py) * (qx - px) / (qy - py) {
+            inside = !inside;
         }
     }
+
+    inside
 }
 
-fn segment_intersection(seg1: Segment, seg2: Segment) -> Option<Point> {
-    let a = seg1[0];
-    let c = seg2[0];
-    let r = seg1[1] - a;
-    let s = seg2[1] - c;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inside() {
+        let square = vec![Point::new(1.0, 1.0), Point::new(4.0, 1.0), Point::new(4.0, 4.0), Point::new(1.0, 4.0)];
+
+        assert!(is_inside(&square, 1.0, 1.0));
+        assert!(is_inside(&square, 3.0, 3.0));
+        assert!(!is_inside(&square, 4.0, 4.0));
+        assert!(!is_inside(&square, 0.0, 2.0));
+    }
+
+    #[test]
+    fn test_raster() {
+        let square = vec![Point::new(1.0, 1.0), Point::new(4.0, 1.0), Point::new(4.0, 4.0), Point::new(1.0, 4.0)];
+        let raster = get_raster(&square, (0, 0), (10, 10));
+
+        assert_eq!(raster.len(), 9);
+        assert!(raster.contains(&(1, 1)));
+        assert!(raster.contains(&(3, 3)));
+        assert!(!raster.contains(&(4, 4)));
+        assert!(raster.iter().all(|(x, y)| is_inside(&square, *x as f64, *y as f64)));
+
+        assert_eq!(get_raster(&square, (0, 0), (3, 3)).len(), 4);
+        assert_eq!(get_raster(&square, (2, 2), (10, 10)).len(), 4);
+    }
+
+    #[test]
+    fn test_chunk() {
+        let mut config = MapGenConfig::default();
+        config.width = 40;
+        config.height = 30;
+        config.points = 30;
+        config.seed = Some(7);
+
+        let map_gen = MapGen::new(&config);
+        let chunk = map_gen.gen_chunk((1, 1), 16);
+
+        assert_eq!(chunk.len(), 256);
+        assert_eq!(chunk.iter().map(|tile| tile.terrain).collect::<Vec<u8>>(), MapGen::new(&config).gen_chunk((1, 1), 16).iter().map(|tile| tile.terrain).collect::<Vec<u8>>());
+        assert_eq!(chunk[15 * 16].terrain, TERRAIN_SEA);
+    }
+
+    #[test]
+    fn test_chunk_files() {
+        assert!(is_chunk_file("0_0.ron"));
+        assert!(is_chunk_file("12_3.ron"));
+        assert!(!is_chunk_file("12_3.ron.bak"));
+        assert!(!is_chunk_file("mapgen.ron"));
+        assert!(!is_chunk_file("1_2_3.ron"));
+        assert!(!is_chunk_file("1_.ron"));
+
+        let mut config = MapGenConfig::default();
+        let root = Path::new("root");
+
+        for chunks in ["", ".", "..", "/"].iter() {
+            config.chunks = chunks.to_string();
+            assert_eq!(get_chunk_dir(root, &config), root.join("cache").join("chunks"));
+        }
+
+        config.chunks = "../../world".to_string();
+        assert_eq!(get_chunk_dir(root, &config), root.join("cache").join("world"));
+    }
+
+    #[test]
+    fn test_set_buildings() {
+        let entity = get_test_entities(1)[0];
+
+        let chunks = MiscChunks::new(8, 8, 4, None);
+        let workplace_data = AssetWorkplaceData {
+            name: "Test".to_string(),
+            inputs: vec![0],
+            outs: vec![0],
+            duration: 0,
+            terrain: vec![TERRAIN_PLAINS],
+            size: (2, 2),
+            entrance: (0, 1),
+            cost: vec![0],
+        };
+        let origin = get_origin(&Point3::new(3, 4, 0), &workplace_data);
+        let buildings = vec![(entity, origin, &workplace_data)];
+
+        assert_eq!(origin, Point3::new(3, 3, 0));
+
+        let mut tiles = vec![MiscTile::default(); 16];
+        set_buildings((0, 1), &mut tiles, &chunks, &buildings);
+
+        assert_eq!(tiles.iter().filter(|tile| tile.building == Some(entity)).count(), 1);
+        assert!(tiles[chunks.get_index(&Point3::new(3, 4, 0))].entrance);
+
+        let mut tiles = vec![MiscTile::default(); 16];
+        set_buildings((1, 1), &mut tiles, &chunks, &buildings);
 
-    let denom = r.cross(s);
-    if denom == 0.0 { return None; }
+        assert_eq!(tiles[chunks.get_index(&Point3::new(4, 4, 0))].building, Some(entity));
+        assert!(!tiles[chunks.get_index(&Point3::new(4, 4, 0))].entrance);
+        assert!(get_cost(&tiles[chunks.get_index(&Point3::new(4, 4, 0))]).is_none());
 
-    let numer_a = (c - a).cross(s);
-    let numer_c = (c - a).cross(r);
+        let mut tiles = vec![MiscTile::default(); 16];
+        set_buildings((1, 0), &mut tiles, &chunks, &buildings);
 
-    let t = numer_a / denom;
-    let u = numer_c / denom;
+        assert!(tiles.iter().all(|tile| tile.building.is_none()));
+    }
+
+    #[test]
+    fn test_class() {
+        for (i, color) in TERRAIN_COLORS.iter().enumerate() {
+            assert_eq!(get_class(&TERRAIN_COLORS, color), i as u8);
+        }
 
-    if t < 0.0 || t > 1.0 || u < 0.0 || u > 1.0 { return None; }
+        assert_eq!(get_class(&RESOURCE_COLORS, &[5, 5, 5]), 0);
+        assert_eq!(get_class(&TERRAIN_COLORS, &[10, 10, 190]), TERRAIN_SEA);
+    }
 
-    return Some(a + r * t);
-}
\ No newline at end of file
+    #[test]
+    fn test_band() {
+        let neighbors = vec![
+            [1].iter().cloned().collect::<HashSet<usize>>(),
+            [0, 2].iter().cloned().collect(),
+            [1, 3].iter().cloned().collect(),
+            [2].iter().cloned().collect(),
+        ];
+        let seas = [0].iter().cloned().collect::<HashSet<usize>>();
+        let coasts = get_band(&seas, &neighbors, 2, &seas);
+
+        assert_eq!(coasts, [1, 2].iter().cloned().collect::<HashSet<usize>>());
+        assert!(get_band(&seas, &neighbors, 0, &seas).is_empty());
+        assert!(get_band(&HashSet::new(), &neighbors, 2, &seas).is_empty());
+    }
+}
@@ -1,7 +1,9 @@
+use crate::asset::*;
+
 use amethyst::{
     prelude::*,
-    core::{ math::{ Point2, Point3, Vector2, Vector3 }, Transform, },
-    ecs::{ World, WorldExt, Join, SystemData, Entities, Read, ReadExpect, ReadStorage, },
+    core::{ math::{ Point2, Point3, Vector2, Vector3 }, Transform, Parent, HiddenPropagate, },
+    ecs::{ World, WorldExt, Join, SystemData, Entity, Entities, Read, ReadExpect, ReadStorage, },
     assets::{ Loader, AssetStorage, },
     renderer::{
         formats::texture::ImageFormat,
@@ -10,7 +12,7 @@ use amethyst::{
         Texture,
     },
     ui::{
-        Anchor, TtfFormat, UiText, UiTransform
+        Anchor, LineMode, TtfFormat, UiText, UiTransform, UiImage,
     },
     tiles::{
         Tile, TileMap, CoordinateEncoder, MortonEncoder2D, MapStorage, Map, DrawTiles2DBounds, Region,
@@ -18,8 +20,10 @@ use amethyst::{
     window::ScreenDimensions,
 };
 use pathfinding::prelude::{ astar, absdiff };
+use serde::{ Serialize, Deserialize };
 use std::ops::Deref;
-use std::collections::{ HashMap, HashSet };
+use std::collections::{ HashMap, HashSet, BinaryHeap };
+use std::cmp::Reverse;
 
 pub fn get_cover(grid: &Vec<bool>, width: usize, height: usize) -> HashSet<(Point2<usize>, Vector2<usize>)> {
     let mut strips: HashMap<Point2<usize>, Point2<usize>> = HashMap::new();
@@ -78,6 +82,48 @@ impl Default for MiscMapMode {
     }
 }
 
+#[derive(Default)]
+pub struct MiscBuild {
+    pub active: bool,
+    pub variant: u8,
+}
+
+pub struct MiscSelection {
+    pub target: Option<Entity>,
+    pub item: u8,
+}
+impl Default for MiscSelection {
+    fn default() -> Self {
+        MiscSelection {
+            target: None,
+            item: 1,
+        }
+    }
+}
+
+// Definition errors by kind (axis, workplace, item, archetype, action) and notices that
+// are not failures, both shown in the Reload text.
+#[derive(Default)]
+pub struct MiscReload {
+    pub errors: [Vec<String>; 5],
+    pub notices: Vec<String>,
+}
+
+pub const CURVE_DOTS: usize = 32;
+pub const CURVE_WIDTH: f32 = 280.;
+pub const CURVE_HEIGHT: f32 = 180.;
+
+pub struct MiscCurveEditor {
+    pub active: bool,
+    pub axis: usize,
+    pub param: usize,
+    pub status: String,
+    pub modified: HashSet<usize>,
+    pub panel: Entity,
+    pub text: Entity,
+    pub dots: Vec<Entity>,
+}
+
 pub struct MiscTime {
     pub year: u16,
     pub month: u8,
@@ -100,60 +146,375 @@ impl Default for MiscTime {
         }
     }
 }
+impl MiscTime {
+    pub fn get_stamp(&self) -> u64 {
+        let days = (self.year as u64 * 12 + self.month as u64 - 1) * 30 + self.day as u64 - 1;
+        let hours = days * 24 + self.hour as u64 + if self.am { 0 } else { 12 };
+
+        return (hours * 60 + self.mnt as u64) * 60 + self.scnd as u64;
+    }
+}
+
+pub struct MiscSpatialGrid {
+    pub size: f32,
+    pub candidates: usize,
+    pub cells: HashMap<(i32, i32), Vec<(Entity, Vector3<f32>)>>,
+    pub min: (i32, i32),
+    pub max: (i32, i32),
+}
+impl Default for MiscSpatialGrid {
+    fn default() -> Self {
+        MiscSpatialGrid {
+            size: 8.0,
+            candidates: 8,
+            cells: HashMap::new(),
+            min: (i32::max_value(), i32::max_value()),
+            max: (i32::min_value(), i32::min_value()),
+        }
+    }
+}
+impl MiscSpatialGrid {
+    pub fn clear(&mut self) {
+        for cell in self.cells.values_mut() {
+            cell.clear();
+        }
+
+        self.min = (i32::max_value(), i32::max_value());
+        self.max = (i32::min_value(), i32::min_value());
+    }
+
+    pub fn get_cell(&self, position: &Vector3<f32>) -> (i32, i32) {
+        ((position[0] / self.size).floor() as i32, (position[1] / self.size).floor() as i32)
+    }
+
+    pub fn insert(&mut self, entity: Entity, position: &Vector3<f32>) {
+        let cell = self.get_cell(position);
+
+        self.min = (self.min.0.min(cell.0), self.min.1.min(cell.1));
+        self.max = (self.max.0.max(cell.0), self.max.1.max(cell.1));
+
+        self.cells.entry(cell).or_insert_with(Vec::new).push((entity, *position));
+    }
+
+    pub fn get_within<F: Fn(Entity) -> bool>(&self, center: &Vector3<f32>, radius: f32, filter: F) -> Vec<Entity> {
+        let min = self.get_cell(&Vector3::new(center[0] - radius, center[1] - radius, 0.0));
+        let max = self.get_cell(&Vector3::new(center[0] + radius, center[1] + radius, 0.0));
+
+        let mut out = Vec::new();
+
+        for y in min.1.max(self.min.1)..=max.1.min(self.max.1) {
+            for x in min.0.max(self.min.0)..=max.0.min(self.max.0) {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    for (entity, position) in cell.iter() {
+                        if get_distance(center, position) <= radius && filter(*entity) {
+                            out.push(*entity);
+                        }
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    pub fn get_nearest<F: Fn(Entity) -> bool>(&self, center: &Vector3<f32>, k: usize, filter: F) -> Vec<Entity> {
+        if k == 0 || self.min.0 > self.max.0 {
+            return Vec::new();
+        }
+
+        let origin = self.get_cell(center);
+        let reach = (origin.0 - self.min.0)
+            .max(self.max.0 - origin.0)
+            .max(origin.1 - self.min.1)
+            .max(self.max.1 - origin.1);
+
+        let mut found: Vec<(Entity, f32)> = Vec::new();
+
+        for ring in 0..=reach {
+            if found.len() >= k && found[k - 1].1 < (ring - 1) as f32 * self.size {
+                break;
+            }
+
+            for y in (origin.1 - ring)..=(origin.1 + ring) {
+                for x in (origin.0 - ring)..=(origin.0 + ring) {
+                    if (y - origin.1).abs() != ring && (x - origin.0).abs() != ring {
+                        continue;
+                    }
 
-#[derive(Default, Clone)]
+                    if let Some(cell) = self.cells.get(&(x, y)) {
+                        for (entity, position) in cell.iter() {
+                            if filter(*entity) {
+                                found.push((*entity, get_distance(center, position)));
+                            }
+                        }
+                    }
+                }
+            }
+
+            found.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        }
+
+        found.into_iter().take(k).map(|(entity, _)| entity).collect()
+    }
+}
+
+pub fn get_distance(a: &Vector3<f32>, b: &Vector3<f32>) -> f32 {
+    ((a[0] - b[0]).powf(2.0) + (a[1] - b[1]).powf(2.0)).sqrt()
+}
+
+pub const TERRAIN_BEACH: u8 = 0;
+pub const TERRAIN_COAST: u8 = 1;
+pub const TERRAIN_SEA: u8 = 2;
+pub const TERRAIN_PLAINS: u8 = 3;
+pub const TERRAIN_FOREST: u8 = 6;
+pub const TERRAIN_HILLS: u8 = 7;
+pub const TERRAIN_MOUNTAINS: u8 = 8;
+pub const TERRAIN_RIVER: u8 = 9;
+pub const TERRAINS: [u8; 8] = [TERRAIN_BEACH, TERRAIN_COAST, TERRAIN_SEA, TERRAIN_PLAINS, TERRAIN_FOREST, TERRAIN_HILLS, TERRAIN_MOUNTAINS, TERRAIN_RIVER];
+
+pub const LAYER_GROUND: u32 = 0;
+pub const LAYER_RESOURCE: u32 = 1;
+pub const LAYER_BUILDING: u32 = 2;
+pub const LAYER_OVERLAY: u32 = 3;
+pub const LAYERS: u32 = 4;
+
+pub const OVERLAY_NONE: u8 = 0;
+pub const OVERLAY_HIGHLIGHT: u8 = 1;
+pub const OVERLAY_SELECTION: u8 = 2;
+pub const OVERLAY_INVALID: u8 = 3;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct MiscTile {
     pub terrain: u8,
     pub resource: u8,
+    pub quantity: u16,
+    #[serde(skip)]
+    pub building: Option<Entity>,
+    #[serde(skip)]
+    pub entrance: bool,
+    #[serde(skip)]
+    pub overlay: u8,
+}
+impl MiscTile {
+    pub fn get_layer(&self, z: u32) -> MiscTileLayer {
+        match z {
+            LAYER_GROUND => MiscTileLayer { value: self.terrain, flag: false },
+            LAYER_RESOURCE => MiscTileLayer { value: self.resource, flag: self.quantity > 0 },
+            LAYER_BUILDING => MiscTileLayer { value: 0, flag: self.building.is_some() },
+            _ => MiscTileLayer { value: self.overlay, flag: false },
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct MiscTileLayer {
+    pub value: u8,
+    pub flag: bool,
 }
-impl Tile for MiscTile {
-    fn sprite(&self, _: Point3<u32>, world: &World) -> Option<usize> {
-        match world.read_resource::<MiscMapMode>().deref() {
-            MiscMapMode::Terrain => {
-                Some(self.terrain as usize)
+impl Tile for MiscTileLayer {
+    fn sprite(&self, point: Point3<u32>, world: &World) -> Option<usize> {
+        match point[2] {
+            LAYER_GROUND => {
+                Some(self.value as usize)
             }
-            MiscMapMode::Nothing => {
-                if self.resource == 0 {
+            LAYER_RESOURCE => {
+                let resource = match world.read_resource::<MiscMapMode>().deref() {
+                    MiscMapMode::Terrain => return None,
+                    MiscMapMode::Nothing => 0,
+                    MiscMapMode::Amethyst => 1,
+                    MiscMapMode::Gold => 2,
+                    MiscMapMode::Metal => 3,
+                    MiscMapMode::Stone => 4,
+                    MiscMapMode::Coal => 5,
+                };
+
+                if self.value == resource && (resource == 0 || self.flag) {
                     Some(4)
                 } else {
                     Some(5)
                 }
             }
-            MiscMapMode::Amethyst => {
-                if self.resource == 1 {
-                    Some(4)
+            LAYER_BUILDING => {
+                if self.flag {
+                    Some(10)
                 } else {
-                    Some(5)
+                    None
                 }
             }
-            MiscMapMode::Gold => {
-                if self.resource == 2 {
-                    Some(4)
+            _ => {
+                if self.value == OVERLAY_NONE {
+                    None
                 } else {
-                    Some(5)
+                    Some(10 + self.value as usize)
                 }
             }
-            MiscMapMode::Metal => {
-                if self.resource == 3 {
-                    Some(4)
-                } else {
-                    Some(5)
-                }
+        }
+    }
+}
+
+pub struct MiscChunks {
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub chunks: HashMap<(u32, u32), Vec<MiscTile>>,
+    pub entities: HashMap<(u32, u32), Entity>,
+    pub dirty: HashSet<Point3<u32>>,
+    pub overlays: Vec<Point3<u32>>,
+    pub sprite_sheet: Option<SpriteSheetHandle>,
+    pub depth: f32,
+    reference: TileMap<MiscTileLayer, MortonEncoder2D>,
+    flip: f32,
+}
+impl MiscChunks {
+    pub fn new(width: u32, height: u32, size: u32, sprite_sheet: Option<SpriteSheetHandle>) -> Self {
+        let size = size.max(2);
+        let reference = TileMap::<MiscTileLayer, MortonEncoder2D>::new(Vector3::new(size, size, 1), Vector3::new(1, 1, 1), None);
+        let flip = (reference.to_world(&Point3::new(0, 1, 0), None)[1] - reference.to_world(&Point3::new(0, 0, 0), None)[1]).signum();
+        let layers = TileMap::<MiscTileLayer, MortonEncoder2D>::new(Vector3::new(1, 1, LAYERS), Vector3::new(1, 1, 1), None);
+        let depth = -(0..LAYERS)
+            .map(|z| layers.to_world(&Point3::new(0, 0, z), None)[2])
+            .fold(std::f32::NEG_INFINITY, f32::max) - 0.01;
+
+        MiscChunks {
+            size,
+            width: width.max(1),
+            height: height.max(1),
+            chunks: HashMap::new(),
+            entities: HashMap::new(),
+            dirty: HashSet::new(),
+            overlays: Vec::new(),
+            sprite_sheet,
+            depth,
+            reference,
+            flip,
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        ((self.width + self.size - 1) / self.size, (self.height + self.size - 1) / self.size)
+    }
+
+    pub fn get_chunk(&self, point: &Point3<u32>) -> (u32, u32) {
+        (point[0] / self.size, point[1] / self.size)
+    }
+
+    pub fn get_chunk_at(&self, coord: &Vector3<f32>) -> (i64, i64) {
+        let (columns, rows) = self.dimensions();
+
+        (
+            ((coord[0] / self.size as f32) + columns as f32 / 2.0).floor() as i64,
+            ((coord[1] * self.flip / self.size as f32) + rows as f32 / 2.0).floor() as i64,
+        )
+    }
+
+    pub fn get_offset(&self, chunk: (u32, u32)) -> Vector3<f32> {
+        let (columns, rows) = self.dimensions();
+
+        Vector3::new(
+            (chunk.0 as f32 + 0.5 - columns as f32 / 2.0) * self.size as f32,
+            (chunk.1 as f32 + 0.5 - rows as f32 / 2.0) * self.size as f32 * self.flip,
+            0.0,
+        )
+    }
+
+    pub fn get_index(&self, point: &Point3<u32>) -> usize {
+        ((point[1] % self.size) * self.size + point[0] % self.size) as usize
+    }
+
+    pub fn get(&self, point: &Point3<u32>) -> Option<&MiscTile> {
+        self.chunks.get(&self.get_chunk(point))?.get(self.get_index(point))
+    }
+
+    pub fn get_mut(&mut self, point: &Point3<u32>) -> Option<&mut MiscTile> {
+        let chunk = self.get_chunk(point);
+        let index = self.get_index(point);
+
+        if !self.chunks.contains_key(&chunk) {
+            return None;
+        }
+
+        if self.entities.contains_key(&chunk) {
+            self.dirty.insert(*point);
+        }
+
+        self.chunks.get_mut(&chunk)?.get_mut(index)
+    }
+
+    pub fn set_overlay(&mut self, point: &Point3<u32>, overlay: u8) {
+        if let Some(tile) = self.get_mut(point) {
+            tile.overlay = overlay;
+            self.overlays.push(*point);
+        }
+    }
+
+    pub fn clear_overlays(&mut self) {
+        for point in std::mem::replace(&mut self.overlays, Vec::new()) {
+            if let Some(tile) = self.get_mut(&point) {
+                tile.overlay = OVERLAY_NONE;
             }
-            MiscMapMode::Stone => {
-                if self.resource == 4 {
-                    Some(4)
-                } else {
-                    Some(5)
+        }
+    }
+
+    pub fn to_tile(&self, coord: &Vector3<f32>) -> Option<Point3<u32>> {
+        let (cx, cy) = self.get_chunk_at(coord);
+        let (columns, rows) = self.dimensions();
+
+        for (dx, dy) in [(0, 0)].iter().chain(NEIGHBORS.iter()) {
+            let (x, y) = (cx + dx, cy + dy);
+
+            if x >= 0 && y >= 0 && x < columns as i64 && y < rows as i64 {
+                let chunk = (x as u32, y as u32);
+
+                if let Ok(local) = self.reference.to_tile(&(coord - self.get_offset(chunk)), None) {
+                    return Some(Point3::new(chunk.0 * self.size + local[0], chunk.1 * self.size + local[1], local[2]));
                 }
             }
-            MiscMapMode::Coal => {
-                if self.resource == 5 {
-                    Some(4)
-                } else {
-                    Some(5)
+        }
+
+        None
+    }
+
+    pub fn to_world(&self, point: &Point3<u32>) -> Vector3<f32> {
+        let local = Point3::new(point[0] % self.size, point[1] % self.size, point[2]);
+
+        self.reference.to_world(&local, None) + self.get_offset(self.get_chunk(point))
+    }
+
+    pub fn get_map(&self, chunk: (u32, u32)) -> Option<TileMap<MiscTileLayer, MortonEncoder2D>> {
+        let mut map = TileMap::<MiscTileLayer, MortonEncoder2D>::new(
+            Vector3::new(self.size, self.size, LAYERS),
+            Vector3::new(1, 1, 1),
+            self.sprite_sheet.clone(),
+        );
+
+        if self.set_map(chunk, &mut map) {
+            Some(map)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_map(&self, chunk: (u32, u32), map: &mut TileMap<MiscTileLayer, MortonEncoder2D>) -> bool {
+        if let Some(tiles) = self.chunks.get(&chunk) {
+            for y in 0..self.size {
+                for x in 0..self.size {
+                    for z in 0..LAYERS {
+                        *map.get_mut(&Point3::new(x, y, z)).unwrap() = tiles[(y * self.size + x) as usize].get_layer(z);
+                    }
                 }
             }
+
+            return true;
+        }
+
+        false
+    }
+
+    pub fn set_point(&self, point: &Point3<u32>, map: &mut TileMap<MiscTileLayer, MortonEncoder2D>) {
+        if let Some(tile) = self.get(point) {
+            for z in 0..LAYERS {
+                *map.get_mut(&Point3::new(point[0] % self.size, point[1] % self.size, z)).unwrap() = tile.get_layer(z);
+            }
         }
     }
 }
@@ -166,14 +527,20 @@ impl DrawTiles2DBounds for MiscTileBounds {
         
         assert!(camera_fetch.is_some());
 
-        let (entities, active_camera, dimensions, transforms, cameras) =
+        let (entities, active_camera, dimensions, transforms, cameras, tilemaps) =
             <(
                 Entities<'_>,
                 Read<'_, ActiveCamera>,
                 ReadExpect<'_, ScreenDimensions>,
                 ReadStorage<'_, Transform>,
                 ReadStorage<'_, Camera>,
+                ReadStorage<'_, TileMap<T, E>>,
             )>::fetch(world);
+
+        let offset = (&tilemaps, &transforms)
+            .join()
+            .find(|(other, _)| std::ptr::eq(*other, map))
+            .map_or(Vector3::new(0.0, 0.0, 0.0), |(_, transform)| *transform.translation());
         
         let mut camera_join = (&cameras, &transforms).join();
         if let Some((camera, camera_transform)) = active_camera
@@ -187,7 +554,7 @@ impl DrawTiles2DBounds for MiscTileBounds {
                     Vector2::new(dimensions.width(), dimensions.height()),
                     camera_transform,
                 );
-            let top_left = Vector3::new(coord[0], coord[1], coord[2]);
+            let top_left = Vector3::new(coord[0], coord[1], coord[2]) - offset;
 
             let coord = camera.projection()
                 .screen_to_world_point(
@@ -195,32 +562,41 @@ impl DrawTiles2DBounds for MiscTileBounds {
                     Vector2::new(dimensions.width(), dimensions.height()),
                     camera_transform,
                 );
-            let bottom_right = Vector3::new(coord[0], coord[1], coord[2]);
+            let bottom_right = Vector3::new(coord[0], coord[1], coord[2]) - offset;
 
             let half_dimensions = Vector2::new(
                 (map.tile_dimensions().x * map.dimensions().x) as f32 / 2.0,
                 (map.tile_dimensions().x * map.dimensions().y) as f32 / 2.0,
             );
-            let bottom_right = Vector3::new(
-                bottom_right
+
+            if top_left.x.max(bottom_right.x) < -half_dimensions.x
+                || top_left.x.min(bottom_right.x) > half_dimensions.x
+                || top_left.y.max(bottom_right.y) < -half_dimensions.y
+                || top_left.y.min(bottom_right.y) > half_dimensions.y
+            {
+                return Region::empty();
+            }
+
+            let clamp = |corner: Vector3<f32>| Vector3::new(
+                corner
                     .x
                     .min(half_dimensions.x - map.tile_dimensions().x as f32)
                     .max(-half_dimensions.x),
-                bottom_right
+                corner
                     .y
                     .min(half_dimensions.y - map.tile_dimensions().y as f32)
                     .max(-half_dimensions.y + map.tile_dimensions().y as f32),
-                bottom_right
+                corner
                     .z
                     .min(-0.0)
                     .max(0.0),
             );
             
             let min = map
-                .to_tile(&top_left, None)
+                .to_tile(&clamp(top_left), None)
                 .unwrap_or_else(|_| Point3::new(0, 0, 0));
             let max = map
-                .to_tile(&bottom_right, None)
+                .to_tile(&clamp(bottom_right), None)
                 .unwrap_or_else(|_| Point3::new(map.dimensions().x - 1, map.dimensions().y - 1, 0));
                 
             Region::new(min, max)
@@ -251,6 +627,141 @@ pub fn load_ui(world: &mut World) {
             [1., 1., 1., 1.],
             50.,
         )).build();
+
+    let transform = UiTransform::new(
+        "Status".to_string(), Anchor::BottomLeft, Anchor::BottomLeft,
+        0., 30., 1., 1200., 30.,
+    );
+
+    world
+        .create_entity()
+        .with(transform)
+        .with(UiText::new(
+            font.clone(),
+            "".to_string(),
+            [1., 1., 0.6, 1.],
+            20.,
+        )).build();
+
+    let transform = UiTransform::new(
+        "Panel".to_string(), Anchor::BottomLeft, Anchor::BottomLeft,
+        0., 0., 1., 1200., 30.,
+    );
+
+    world
+        .create_entity()
+        .with(transform)
+        .with(UiText::new(
+            font.clone(),
+            "".to_string(),
+            [1., 1., 1., 1.],
+            25.,
+        )).build();
+
+    let transform = UiTransform::new(
+        "Inspector".to_string(), Anchor::TopRight, Anchor::TopRight,
+        0., 0., 1., 450., 600.,
+    );
+    let mut text = UiText::new(
+        font.clone(),
+        "".to_string(),
+        [1., 1., 1., 1.],
+        20.,
+    );
+    text.line_mode = LineMode::Wrap;
+    text.align = Anchor::TopLeft;
+
+    world
+        .create_entity()
+        .with(transform)
+        .with(text)
+        .build();
+
+    let transform = UiTransform::new(
+        "Build".to_string(), Anchor::TopLeft, Anchor::TopLeft,
+        0., 0., 1., 400., 30.,
+    );
+
+    world
+        .create_entity()
+        .with(transform)
+        .with(UiText::new(
+            font.clone(),
+            "".to_string(),
+            [1., 1., 1., 1.],
+            25.,
+        )).build();
+
+    let transform = UiTransform::new(
+        "Reload".to_string(), Anchor::TopLeft, Anchor::TopLeft,
+        0., -30., 1., 800., 200.,
+    );
+    let mut text = UiText::new(
+        font.clone(),
+        "".to_string(),
+        [1., 0.3, 0.3, 1.],
+        20.,
+    );
+    text.line_mode = LineMode::Wrap;
+    text.align = Anchor::TopLeft;
+
+    world
+        .create_entity()
+        .with(transform)
+        .with(text)
+        .build();
+
+    let panel = world
+        .create_entity()
+        .with(UiTransform::new(
+            "CurveEditor".to_string(), Anchor::BottomRight, Anchor::BottomRight,
+            0., 0., 1., CURVE_WIDTH + 20., CURVE_HEIGHT + 80.,
+        ))
+        .with(UiImage::SolidColor([0., 0., 0., 0.8]))
+        .with(HiddenPropagate::default())
+        .build();
+
+    let mut text = UiText::new(
+        font.clone(),
+        "".to_string(),
+        [1., 1., 1., 1.],
+        16.,
+    );
+    text.line_mode = LineMode::Wrap;
+    text.align = Anchor::TopLeft;
+
+    let text = world
+        .create_entity()
+        .with(UiTransform::new(
+            "CurveEditorText".to_string(), Anchor::TopLeft, Anchor::TopLeft,
+            10., -5., 2., CURVE_WIDTH, 60.,
+        ))
+        .with(text)
+        .with(Parent { entity: panel })
+        .build();
+
+    let dots = (0..CURVE_DOTS).map(|i| {
+        world
+            .create_entity()
+            .with(UiTransform::new(
+                format!("CurveEditorDot{}", i), Anchor::BottomLeft, Anchor::Middle,
+                0., 0., 2., 4., 4.,
+            ))
+            .with(UiImage::SolidColor([1., 1., 0., 1.]))
+            .with(Parent { entity: panel })
+            .build()
+    }).collect();
+
+    world.insert(MiscCurveEditor {
+        active: false,
+        axis: 0,
+        param: 0,
+        status: "".to_string(),
+        modified: HashSet::new(),
+        panel,
+        text,
+        dots,
+    });
 }
 
 pub fn load_sprite_sheet(world: &mut World, png_path: &str, ron_path: &str) -> SpriteSheetHandle {
@@ -283,91 +794,142 @@ pub fn load_sprite_sheet_system(
     loader.load(ron_path, SpriteSheetFormat(texture_handle), (), sprite_sheet_store)
 }
 
-pub fn get_targets(start: &Point3<u32>, goal: &Point3<u32>, tilemap: &TileMap<MiscTile, MortonEncoder2D>) -> Vec<Point3<u32>> {
-    let dimensions = tilemap.dimensions();
+const NEIGHBORS: [(i64, i64); 8] = [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (1, 1), (-1, 1)];
+
+pub fn get_cost(tile: &MiscTile) -> Option<u32> {
+    if tile.building.is_some() && !tile.entrance {
+        return None;
+    }
+
+    match tile.terrain {
+        TERRAIN_BEACH | TERRAIN_PLAINS => Some(1),
+        TERRAIN_FOREST => Some(2),
+        TERRAIN_HILLS => Some(3),
+        TERRAIN_RIVER => Some(4),
+        _ => None,
+    }
+}
+
+pub fn is_site(tile: &MiscTile, workplace_data: &AssetWorkplaceData) -> bool {
+    workplace_data.terrain.contains(&tile.terrain)
+}
 
+pub fn get_footprint(origin: &Point3<u32>, workplace_data: &AssetWorkplaceData) -> Vec<Point3<u32>> {
     let mut out = Vec::new();
 
-    if let Some((targets, _)) = astar(
-        start,
-        |&node| {
-            let mut out = Vec::new();
-            
-            if node[0] >= 1 {
-                let point = Point3::new(node[0] - 1, node[1], node[2]);
+    for y in 0..workplace_data.size.1 {
+        for x in 0..workplace_data.size.0 {
+            out.push(Point3::new(origin[0] + x, origin[1] + y, origin[2]));
+        }
+    }
 
-                if tilemap.get(&point).unwrap().terrain == 0 || tilemap.get(&point).unwrap().terrain == 3 {
-                    out.push((point, 1));
-                }
-            }
-            if node[0] + 1 < dimensions[0] {
-                let point = Point3::new(node[0] + 1, node[1], node[2]);
+    out
+}
 
-                if tilemap.get(&point).unwrap().terrain == 0 || tilemap.get(&point).unwrap().terrain == 3 {
-                    out.push((point, 1));
-                }
-            }
-            if node[1] >= 1 {
-                let point = Point3::new(node[0], node[1] - 1, node[2]);
+pub fn get_entrance(origin: &Point3<u32>, workplace_data: &AssetWorkplaceData) -> Point3<u32> {
+    Point3::new(origin[0] + workplace_data.entrance.0, origin[1] + workplace_data.entrance.1, origin[2])
+}
 
-                if tilemap.get(&point).unwrap().terrain == 0 || tilemap.get(&point).unwrap().terrain == 3 {
-                    out.push((point, 1));
-                }
-            }
-            if node[1] + 1 < dimensions[1] {
-                let point = Point3::new(node[0], node[1] + 1, node[2]);
+pub fn get_origin(entrance: &Point3<u32>, workplace_data: &AssetWorkplaceData) -> Point3<u32> {
+    Point3::new(entrance[0] - workplace_data.entrance.0, entrance[1] - workplace_data.entrance.1, entrance[2])
+}
 
-                if tilemap.get(&point).unwrap().terrain == 0 || tilemap.get(&point).unwrap().terrain == 3 {
-                    out.push((point, 1));
-                }
-            }
-            if node[0] >= 1 && node[1] >= 1 {
-                let point = Point3::new(node[0] - 1, node[1] - 1, node[2]);
+pub fn can_place(origin: &Point3<u32>, workplace_data: &AssetWorkplaceData, chunks: &MiscChunks) -> bool {
+    get_footprint(origin, workplace_data).iter().all(|point| {
+        point[0] < chunks.width && point[1] < chunks.height && chunks.get(point).map_or(false, |tile| tile.building.is_none() && is_site(tile, workplace_data))
+    })
+}
 
-                if tilemap.get(&point).unwrap().terrain == 0 || tilemap.get(&point).unwrap().terrain == 3 {
-                    out.push((point, 1));
-                }
-            }
-            if node[0] + 1 < dimensions[0] && node[1] >= 1 {
-                let point = Point3::new(node[0] + 1, node[1] - 1, node[2]);
+pub fn place(origin: &Point3<u32>, workplace_data: &AssetWorkplaceData, entity: Entity, chunks: &mut MiscChunks) {
+    let entrance = get_entrance(origin, workplace_data);
 
-                if tilemap.get(&point).unwrap().terrain == 0 || tilemap.get(&point).unwrap().terrain == 3 {
-                    out.push((point, 1));
-                }
-            }
-            if node[0] + 1 < dimensions[0] && node[1] + 1 < dimensions[1] {
-                let point = Point3::new(node[0] + 1, node[1] + 1, node[2]);
+    for point in get_footprint(origin, workplace_data) {
+        if let Some(tile) = chunks.get_mut(&point) {
+            tile.building = Some(entity);
+            tile.entrance = point == entrance;
+        }
+    }
+}
 
-                if tilemap.get(&point).unwrap().terrain == 0 || tilemap.get(&point).unwrap().terrain == 3 {
-                    out.push((point, 1));
-                }
-            }
-            if node[0] >= 1 && node[1] + 1 < dimensions[1] {
-                let point = Point3::new(node[0] - 1, node[1] + 1, node[2]);
+pub fn can_pay(items: &[u16], cost: &[u16]) -> bool {
+    cost.iter().enumerate().all(|(i, cost)| items.get(i).map_or(*cost == 0, |item| item.checked_sub(*cost).is_some()))
+}
 
-                if tilemap.get(&point).unwrap().terrain == 0 || tilemap.get(&point).unwrap().terrain == 3 {
-                    out.push((point, 1));
-                }
+pub fn pay(items: &mut [u16], cost: &[u16]) -> bool {
+    if !can_pay(items, cost) {
+        return false;
+    }
+
+    for (item, cost) in items.iter_mut().zip(cost.iter()) {
+        *item = item.saturating_sub(*cost);
+    }
+
+    true
+}
+
+pub fn get_neighbors(node: &Point3<u32>, chunks: &MiscChunks) -> Vec<(Point3<u32>, u32)> {
+    let mut out = Vec::new();
+
+    for (dx, dy) in NEIGHBORS.iter() {
+        let x = node[0] as i64 + dx;
+        let y = node[1] as i64 + dy;
+
+        if x >= 0 && y >= 0 && x < chunks.width as i64 && y < chunks.height as i64 {
+            let point = Point3::new(x as u32, y as u32, node[2]);
+
+            if let Some(cost) = chunks.get(&point).and_then(get_cost) {
+                out.push((point, cost));
             }
+        }
+    }
 
-            out
-        },
+    out
+}
+
+pub fn get_targets(start: &Point3<u32>, goal: &Point3<u32>, chunks: &MiscChunks) -> Vec<Point3<u32>> {
+    let mut out = Vec::new();
+
+    if let Some((targets, _)) = astar(
+        start,
+        |&node| get_neighbors(&node, chunks),
         |&node| absdiff(node[0], goal[0]) + absdiff(node[1], goal[1]),
         |&node| node == *goal
     ) {
-        for (i, target) in targets.iter().rev().enumerate() {
-            let i = targets.len() - i - 1;
+        out = get_waypoints(&targets);
+    }
 
-            if i == 0 || i + 1 == targets.len() {
-                out.push(*target);
-            } else {
-                let t0 = targets[i + 1];
-                let t1 = targets[i - 1];
-                let t2 = Point3::new(t0[0] + t1[0], t0[1] + t1[1], t0[2] + t1[2]);
+    out
+}
 
-                if t2 != target * 2 {
-                    out.push(*target);
-                }
+pub fn get_flow_targets(start: &Point3<u32>, field: &MiscTravelField) -> Vec<Point3<u32>> {
+    let mut targets = vec![*start];
+
+    if field.get_distance(start).is_none() {
+        return Vec::new();
+    }
+
+    while let Some(next) = field.get_next(targets.last().unwrap()) {
+        targets.push(next);
+    }
+
+    get_waypoints(&targets)
+}
+
+fn get_waypoints(targets: &Vec<Point3<u32>>) -> Vec<Point3<u32>> {
+    let mut out = Vec::new();
+
+    for (i, target) in targets.iter().rev().enumerate() {
+        let i = targets.len() - i - 1;
+
+        if i == 0 || i + 1 == targets.len() {
+            out.push(*target);
+        } else {
+            let t0 = targets[i + 1];
+            let t1 = targets[i - 1];
+            let t2 = Point3::new(t0[0] + t1[0], t0[1] + t1[1], t0[2] + t1[2]);
+
+            if t2 != target * 2 {
+                out.push(*target);
             }
         }
     }
@@ -375,6 +937,119 @@ pub fn get_targets(start: &Point3<u32>, goal: &Point3<u32>, tilemap: &TileMap<Mi
     out
 }
 
+pub struct MiscTravelField {
+    pub goal: Point3<u32>,
+    pub size: u32,
+    pub distances: HashMap<(u32, u32), Vec<u32>>,
+    pub flows: HashMap<(u32, u32), Vec<u8>>,
+    pub region: HashSet<(u32, u32)>,
+}
+impl MiscTravelField {
+    fn get_key(&self, point: &Point3<u32>) -> ((u32, u32), usize) {
+        ((point[0] / self.size, point[1] / self.size), ((point[1] % self.size) * self.size + point[0] % self.size) as usize)
+    }
+
+    pub fn get_distance(&self, point: &Point3<u32>) -> Option<u32> {
+        let (chunk, index) = self.get_key(point);
+
+        match self.distances.get(&chunk)?.get(index) {
+            Some(distance) if *distance != u32::max_value() => Some(*distance),
+            _ => None,
+        }
+    }
+
+    pub fn get_next(&self, point: &Point3<u32>) -> Option<Point3<u32>> {
+        let (chunk, index) = self.get_key(point);
+        let (dx, dy) = NEIGHBORS.get(*self.flows.get(&chunk)?.get(index)? as usize)?;
+
+        Some(Point3::new((point[0] as i64 + dx) as u32, (point[1] as i64 + dy) as u32, point[2]))
+    }
+
+    fn set(&mut self, point: &Point3<u32>, distance: u32, flow: u8) {
+        let (chunk, index) = self.get_key(point);
+        let area = (self.size * self.size) as usize;
+
+        self.distances.entry(chunk).or_insert_with(|| vec![u32::max_value(); area])[index] = distance;
+        self.flows.entry(chunk).or_insert_with(|| vec![u8::max_value(); area])[index] = flow;
+    }
+}
+
+#[derive(Default)]
+pub struct MiscTravel {
+    pub fields: HashMap<Entity, MiscTravelField>,
+    pub costs: HashMap<(u32, u32), Vec<u8>>,
+    pub changed: HashSet<(u32, u32)>,
+}
+impl MiscTravel {
+    pub fn invalidate(&mut self) {
+        for chunk in std::mem::replace(&mut self.changed, HashSet::new()) {
+            self.costs.remove(&chunk);
+            self.fields.retain(|_, field| !field.region.contains(&chunk));
+        }
+    }
+}
+
+pub fn get_costs(tiles: &[MiscTile]) -> Vec<u8> {
+    tiles.iter().map(|tile| get_cost(tile).map_or(0, |cost| cost as u8)).collect()
+}
+
+// Fields cover the terrain whether or not its chunk is loaded, chunks missing from
+// memory are read through `load` and only their step costs are kept.
+pub fn get_travel_field<F: FnMut((u32, u32)) -> Option<Vec<MiscTile>>>(goal: &Point3<u32>, chunks: &MiscChunks, costs: &mut HashMap<(u32, u32), Vec<u8>>, mut load: F) -> MiscTravelField {
+    let mut field = MiscTravelField {
+        goal: *goal,
+        size: chunks.size,
+        distances: HashMap::new(),
+        flows: HashMap::new(),
+        region: HashSet::new(),
+    };
+    let mut heap = BinaryHeap::new();
+    let area = (chunks.size * chunks.size) as usize;
+
+    field.set(goal, 0, u8::max_value());
+    heap.push(Reverse((0, goal[0], goal[1])));
+
+    while let Some(Reverse((distance, x, y))) = heap.pop() {
+        if distance > field.get_distance(&Point3::new(x, y, goal[2])).unwrap_or(u32::max_value()) {
+            continue;
+        }
+
+        for (dx, dy) in NEIGHBORS.iter() {
+            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+
+            if nx < 0 || ny < 0 || nx >= chunks.width as i64 || ny >= chunks.height as i64 {
+                continue;
+            }
+
+            let point = Point3::new(nx as u32, ny as u32, goal[2]);
+            let chunk = chunks.get_chunk(&point);
+
+            field.region.insert(chunk);
+
+            let cost = costs.entry(chunk).or_insert_with(|| match chunks.chunks.get(&chunk) {
+                Some(tiles) => get_costs(tiles),
+                None => load(chunk).map_or_else(|| vec![0; area], |tiles| get_costs(&tiles)),
+            })[chunks.get_index(&point)] as u32;
+
+            if cost == 0 {
+                continue;
+            }
+
+            if distance + cost < field.get_distance(&point).unwrap_or(u32::max_value()) {
+                let flow = NEIGHBORS
+                    .iter()
+                    .position(|(dx, dy)| point[0] as i64 + dx == x as i64 && point[1] as i64 + dy == y as i64)
+                    .unwrap() as u8;
+
+                field.set(&point, distance + cost, flow);
+                heap.push(Reverse((distance + cost, point[0], point[1])));
+            }
+        }
+    }
+
+    field
+}
+
 pub fn get_indiff(k: f32, x: u16, a0: f32, a1: f32, p0: f32, p1: f32) -> u16 {
     return ((k - (a0 * x as f32).powf(p0)).powf(1.0 / p1) / a1).ceil() as u16;
 }
@@ -392,6 +1067,14 @@ pub fn get_price(buying: bool, amount: u16, currency: (u16, f32, f32), item: (u1
     }
 }
 
+// Entities only need to be distinct for most tests, the world that made them can go away.
+#[cfg(test)]
+pub fn get_test_entities(count: usize) -> Vec<Entity> {
+    let mut world = World::new();
+
+    (0..count).map(|_| world.create_entity().build()).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -402,6 +1085,166 @@ mod tests {
         assert!(get_util(4, 9.7331254, 0.586978216) <= 8.57982999635 + 0.001 || get_util(4, 9.7331254, 0.586978216) >= 8.57982999635 - 0.001);
     }
 
+    #[test]
+    fn test_time_stamp() {
+        let mut time = MiscTime::default();
+        let start = time.get_stamp();
+
+        time.scnd = 1;
+        assert_eq!(time.get_stamp(), start + 1);
+
+        time.am = false;
+        assert!(time.get_stamp() > start + 1);
+
+        time = MiscTime { year: 1, month: 1, day: 1, hour: 0, mnt: 0, scnd: 0, am: true };
+        assert!(time.get_stamp() > start);
+    }
+
+    #[test]
+    fn test_spatial_grid() {
+        let entities = get_test_entities(3);
+        let (a, b, c) = (entities[0], entities[1], entities[2]);
+
+        let mut grid = MiscSpatialGrid::default();
+
+        grid.insert(a, &Vector3::new(1.0, 1.0, 0.0));
+        grid.insert(b, &Vector3::new(20.0, 3.0, 0.0));
+        grid.insert(c, &Vector3::new(-30.0, -30.0, 0.0));
+
+        assert_eq!(grid.get_within(&Vector3::new(0.0, 0.0, 0.0), 5.0, |_| true), vec![a]);
+        assert_eq!(grid.get_nearest(&Vector3::new(15.0, 0.0, 0.0), 2, |_| true), vec![b, a]);
+        assert_eq!(grid.get_nearest(&Vector3::new(15.0, 0.0, 0.0), 1, |e| e != b), vec![a]);
+        assert_eq!(grid.get_nearest(&Vector3::new(0.0, 0.0, 0.0), 5, |_| true).len(), 3);
+    }
+
+    #[test]
+    fn test_travel_field() {
+        let mut chunks = MiscChunks::new(5, 5, 4, None);
+
+        for chunk in vec![(0, 0), (1, 0), (0, 1), (1, 1)] {
+            chunks.chunks.insert(chunk, vec![MiscTile::default(); 16]);
+        }
+
+        for y in 0..4 {
+            chunks.get_mut(&Point3::new(1, y, 0)).unwrap().terrain = TERRAIN_SEA;
+        }
+
+        let field = get_travel_field(&Point3::new(0, 0, 0), &chunks, &mut HashMap::new(), |_| None);
+
+        assert_eq!(field.get_distance(&Point3::new(0, 0, 0)), Some(0));
+        assert_eq!(field.get_distance(&Point3::new(0, 3, 0)), Some(3));
+        assert_eq!(field.get_distance(&Point3::new(2, 0, 0)), Some(8));
+        assert_eq!(field.get_distance(&Point3::new(1, 0, 0)), None);
+        assert_eq!(field.get_next(&Point3::new(0, 0, 0)), None);
+        assert_eq!(field.get_next(&Point3::new(0, 1, 0)), Some(Point3::new(0, 0, 0)));
+
+        let targets = get_flow_targets(&Point3::new(2, 0, 0), &field);
+
+        assert_eq!(targets.first(), Some(&Point3::new(0, 0, 0)));
+        assert_eq!(targets.last(), Some(&Point3::new(2, 0, 0)));
+        assert!(get_flow_targets(&Point3::new(1, 0, 0), &field).is_empty());
+        assert!(chunks.get(&Point3::new(8, 0, 0)).is_none());
+        assert_eq!(chunks.to_tile(&chunks.to_world(&Point3::new(6, 2, 0))), Some(Point3::new(6, 2, 0)));
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut chunks = MiscChunks::new(8, 8, 4, None);
+        chunks.chunks.insert((0, 0), vec![MiscTile { terrain: TERRAIN_FOREST, resource: 2, quantity: 5, ..MiscTile::default() }; 16]);
+
+        let tile = chunks.get(&Point3::new(1, 1, 0)).unwrap();
+
+        assert_eq!(tile.get_layer(LAYER_GROUND), MiscTileLayer { value: TERRAIN_FOREST, flag: false });
+        assert_eq!(tile.get_layer(LAYER_RESOURCE), MiscTileLayer { value: 2, flag: true });
+        assert_eq!(tile.get_layer(LAYER_BUILDING), MiscTileLayer { value: 0, flag: false });
+
+        chunks.set_overlay(&Point3::new(1, 1, 0), OVERLAY_HIGHLIGHT);
+        assert!(chunks.dirty.is_empty());
+
+        chunks.entities.insert((0, 0), get_test_entities(1)[0]);
+        chunks.set_overlay(&Point3::new(2, 1, 0), OVERLAY_SELECTION);
+
+        assert_eq!(chunks.dirty.len(), 1);
+        assert!(chunks.dirty.contains(&Point3::new(2, 1, 0)));
+        assert_eq!(chunks.get(&Point3::new(2, 1, 0)).unwrap().get_layer(LAYER_OVERLAY).value, OVERLAY_SELECTION);
+    }
+
+    #[test]
+    fn test_travel_unloaded() {
+        let workplace = get_test_entities(1)[0];
+
+        let mut chunks = MiscChunks::new(12, 4, 4, None);
+        chunks.chunks.insert((0, 0), vec![MiscTile { terrain: TERRAIN_PLAINS, ..MiscTile::default() }; 16]);
+        chunks.chunks.insert((2, 0), vec![MiscTile { terrain: TERRAIN_PLAINS, ..MiscTile::default() }; 16]);
+
+        let mut travel = MiscTravel::default();
+        let field = get_travel_field(&Point3::new(0, 0, 0), &chunks, &mut travel.costs, |_| Some(vec![MiscTile { terrain: TERRAIN_PLAINS, ..MiscTile::default() }; 16]));
+
+        assert_eq!(field.get_distance(&Point3::new(11, 0, 0)), Some(11));
+        assert!(field.region.contains(&(1, 0)));
+        assert_eq!(travel.costs.len(), 3);
+
+        travel.fields.insert(workplace, field);
+        travel.changed.insert((5, 5));
+        travel.invalidate();
+
+        assert!(travel.fields.contains_key(&workplace));
+
+        travel.changed.insert((1, 0));
+        travel.invalidate();
+
+        assert!(travel.fields.is_empty());
+        assert_eq!(travel.costs.len(), 2);
+    }
+
+    #[test]
+    fn test_footprint() {
+        let entity = get_test_entities(1)[0];
+
+        let mut chunks = MiscChunks::new(8, 8, 4, None);
+
+        for chunk in vec![(0, 0), (1, 0), (0, 1), (1, 1)] {
+            chunks.chunks.insert(chunk, vec![MiscTile { terrain: TERRAIN_PLAINS, ..MiscTile::default() }; 16]);
+        }
+
+        let workplace_data = AssetWorkplaceData {
+            name: "Test".to_string(),
+            inputs: vec![0],
+            outs: vec![0],
+            duration: 0,
+            terrain: vec![TERRAIN_PLAINS],
+            size: (2, 2),
+            entrance: (0, 1),
+            cost: vec![0],
+        };
+
+        assert!(can_place(&Point3::new(3, 3, 0), &workplace_data, &chunks));
+        assert!(!can_place(&Point3::new(7, 3, 0), &workplace_data, &chunks));
+
+        place(&Point3::new(3, 3, 0), &workplace_data, entity, &mut chunks);
+
+        assert!(!can_place(&Point3::new(4, 4, 0), &workplace_data, &chunks));
+        assert_eq!(get_cost(chunks.get(&Point3::new(4, 3, 0)).unwrap()), None);
+        assert_eq!(get_cost(chunks.get(&Point3::new(3, 4, 0)).unwrap()), Some(1));
+
+        let field = get_travel_field(&get_entrance(&Point3::new(3, 3, 0), &workplace_data), &chunks, &mut HashMap::new(), |_| None);
+
+        assert_eq!(field.get_distance(&Point3::new(4, 4, 0)), None);
+        assert_eq!(field.get_distance(&Point3::new(5, 4, 0)), Some(2));
+    }
+
+    #[test]
+    fn test_pay() {
+        let mut items = [10, 2, 0, 0];
+
+        assert!(can_pay(&items, &[10, 2]));
+        assert!(!can_pay(&items, &[0, 0, 0, 0, 1]));
+        assert!(!pay(&mut items, &[5, 3]));
+        assert_eq!(items, [10, 2, 0, 0]);
+        assert!(pay(&mut items, &[4, 2, 0, 0, 0]));
+        assert_eq!(items, [6, 0, 0, 0]);
+    }
+
     #[test]
     fn test_indiff() {
         assert_eq!(get_indiff(5.0, 2, 5.0, 1.0, 0.5, 0.5), 4);
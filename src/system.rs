@@ -3,6 +3,8 @@ use crate::component::*;
 use crate::ai::*;
 use crate::asset::*;
 use crate::ui::*;
+use crate::map::*;
+use crate::validate::*;
 use crate::NUM_ITEM;
 
 use amethyst::{
@@ -16,7 +18,7 @@ use amethyst::{
         Transform, ParentHierarchy, HiddenPropagate, Parent,
     },
     derive::SystemDesc,
-    ecs::{ Entity, Entities, System, SystemData, ReadStorage, WriteStorage, Read, ReadExpect, Write, Join, ParJoin, },
+    ecs::{ Entity, Entities, System, SystemData, ReadStorage, WriteStorage, Read, ReadExpect, Write, WriteExpect, Join, ParJoin, },
     input::{ InputHandler, StringBindings, VirtualKeyCode, InputEvent }, 
     renderer::{
         camera::{ ActiveCamera, Camera, },
@@ -26,14 +28,14 @@ use amethyst::{
     },
     window::ScreenDimensions,
     winit,
-    tiles::{
-        TileMap, Map,
-    },
+    utils::application_root_dir,
+    tiles::TileMap,
 };
 use rayon::iter::ParallelIterator;
-use rand::prelude::*;
-use rand::distributions::WeightedIndex;
-use std::collections::HashMap;
+use ron::ser::{ to_string_pretty, PrettyConfig };
+use std::collections::{ HashMap, HashSet, VecDeque };
+use std::fs::{ write, metadata };
+use std::path::PathBuf;
 
 #[derive(Debug, SystemDesc)]
 #[system_desc(name(SystemCustomUiDesc))]
@@ -259,12 +261,12 @@ impl<'s> System<'s> for SystemMovementPlayer {
         ReadExpect<'s, ScreenDimensions>,
         ReadStorage<'s, Transform>,
         ReadStorage<'s, Camera>,
-        ReadStorage<'s, TileMap<MiscTile>>,
+        ReadExpect<'s, MiscChunks>,
         ReadStorage<'s, ComponentPlayerControlled>,
         WriteStorage<'s, ComponentMovement>,
     );
 
-    fn run(&mut self, (entities, events, input, active_camera, dimensions, transforms, cameras, tilemaps, player, mut movements): Self::SystemData) {
+    fn run(&mut self, (entities, events, input, active_camera, dimensions, transforms, cameras, chunks, player, mut movements): Self::SystemData) {
         for event in events.read(&mut self.event_reader) {
             if let InputEvent::MouseButtonPressed(button) = *event {
                 match button {
@@ -284,15 +286,14 @@ impl<'s> System<'s> for SystemMovementPlayer {
                                         camera_transform,
                                     );
                                 
-                                let tilemap = (&tilemaps).join().next().unwrap();
                                 let coord = Vector3::new(coord[0], coord[1], coord[2]);
 
-                                if let Ok(goal) = tilemap.to_tile(&coord, None) {
+                                if let Some(goal) = chunks.to_tile(&coord) {
                                     let (_, transform, mut movement) = (&player, &transforms, &mut movements).join().next().unwrap();
                                 
-                                    let start = tilemap.to_tile(transform.translation(), None).unwrap();
+                                    let start = chunks.to_tile(transform.translation()).unwrap();
 
-                                    movement.targets = get_targets(&start, &goal, tilemap);
+                                    movement.targets = get_targets(&start, &goal, &chunks);
                                 }
                             }
                         }
@@ -305,6 +306,747 @@ impl<'s> System<'s> for SystemMovementPlayer {
     }
 }
 
+#[derive(SystemDesc)]
+#[system_desc(name(SystemBuildDesc))]
+pub struct SystemBuild {
+    #[system_desc(event_channel_reader)]
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+}
+impl SystemBuild {
+    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        SystemBuild { event_reader }
+    }
+}
+impl<'s> System<'s> for SystemBuild {
+    type SystemData = (
+        Entities<'s>,
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Read<'s, InputHandler<StringBindings>>,
+        Read<'s, ActiveCamera>,
+        ReadExpect<'s, ScreenDimensions>,
+        Read<'s, Vec<AssetWorkplaceData>>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, ComponentPlayerControlled>,
+        WriteStorage<'s, Transform>,
+        WriteStorage<'s, ComponentWorkplace>,
+        WriteStorage<'s, ComponentStockpile>,
+        WriteStorage<'s, ComponentPrice>,
+        Write<'s, MiscBuild>,
+        WriteExpect<'s, MiscChunks>,
+        Write<'s, MiscTravel>,
+    );
+
+    fn run(&mut self, (entities, events, input, active_camera, dimensions, workplace_datas, ui_finder, mut ui_texts, cameras, player, mut transforms, mut workplaces, mut stockpiles, mut prices, mut build, mut chunks, mut travel): Self::SystemData) {
+        let mut clicked = false;
+
+        for event in events.read(&mut self.event_reader) {
+            match event {
+                InputEvent::ActionPressed(action) if action == "build_mode" => {
+                    build.active = !build.active;
+                }
+                InputEvent::ActionPressed(action) if action == "build_next" => {
+                    build.variant = ((build.variant as usize + 1) % workplace_datas.len().max(1)) as u8;
+                }
+                InputEvent::MouseButtonPressed(winit::MouseButton::Left) => {
+                    clicked = true;
+                }
+                _ => (),
+            }
+        }
+
+        chunks.clear_overlays();
+
+        if let Some(text) = ui_finder.find("Build").and_then(|entity| ui_texts.get_mut(entity)) {
+            text.text = match workplace_datas.get(build.variant as usize) {
+                Some(workplace_data) if build.active => format!("Build: {} {:?}", workplace_data.name, workplace_data.cost),
+                _ => "".to_string(),
+            };
+        }
+
+        if !build.active || workplace_datas.get(build.variant as usize).is_none() {
+            return;
+        }
+
+        let workplace_data = &workplace_datas[build.variant as usize];
+        let mut origin = None;
+
+        if let Some(mouse_position) = input.mouse_position() {
+            let mut camera_join = (&cameras, &transforms).join();
+
+            if let Some((camera, camera_transform)) = active_camera
+                .entity
+                .and_then(|a| camera_join.get(a, &entities))
+                .or_else(|| camera_join.next())
+            {
+                let coord = camera.projection()
+                    .screen_to_world_point(
+                        Point3::new(mouse_position.0, mouse_position.1, 0.0),
+                        Vector2::new(dimensions.width(), dimensions.height()),
+                        camera_transform,
+                    );
+
+                origin = chunks.to_tile(&Vector3::new(coord[0], coord[1], coord[2]));
+            }
+        }
+
+        if let Some(origin) = origin {
+            let payer = (&entities, &player, &stockpiles).join().next().map(|(entity, _, _)| entity);
+            let affordable = payer.and_then(|payer| stockpiles.get(payer)).map_or(false, |stockpile| can_pay(&stockpile.items, &workplace_data.cost));
+            let valid = affordable && can_place(&origin, workplace_data, &chunks);
+
+            for point in get_footprint(&origin, workplace_data) {
+                chunks.set_overlay(&point, if valid { OVERLAY_HIGHLIGHT } else { OVERLAY_INVALID });
+            }
+
+            if valid {
+                chunks.set_overlay(&get_entrance(&origin, workplace_data), OVERLAY_SELECTION);
+            }
+
+            if clicked && valid && pay(&mut stockpiles.get_mut(payer.unwrap()).unwrap().items, &workplace_data.cost) {
+                let footprint = get_footprint(&origin, workplace_data);
+                let center = (chunks.to_world(footprint.first().unwrap()) + chunks.to_world(footprint.last().unwrap())) / 2.0;
+
+                let entity = entities
+                    .build_entity()
+                    .with(Transform::from(center), &mut transforms)
+                    .with(ComponentWorkplace { variant: build.variant, entrance: Some(get_entrance(&origin, workplace_data)) }, &mut workplaces)
+                    .with(ComponentStockpile { items: [0; NUM_ITEM] }, &mut stockpiles)
+                    .with(ComponentPrice {
+                        update: [true; NUM_ITEM],
+                        buy: [0; NUM_ITEM],
+                        sell: [0; NUM_ITEM],
+                        weight: [1.0; NUM_ITEM],
+                        decay: [0.5; NUM_ITEM],
+                    }, &mut prices)
+                    .build();
+
+                chunks.clear_overlays();
+                place(&origin, workplace_data, entity, &mut chunks);
+
+                for point in get_footprint(&origin, workplace_data) {
+                    travel.changed.insert(chunks.get_chunk(&point));
+                }
+            }
+        }
+    }
+}
+
+#[derive(SystemDesc)]
+#[system_desc(name(SystemSelectionDesc))]
+pub struct SystemSelection {
+    #[system_desc(event_channel_reader)]
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+}
+impl SystemSelection {
+    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        SystemSelection { event_reader }
+    }
+}
+impl<'s> System<'s> for SystemSelection {
+    type SystemData = (
+        Entities<'s>,
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Read<'s, InputHandler<StringBindings>>,
+        Read<'s, ActiveCamera>,
+        ReadExpect<'s, ScreenDimensions>,
+        Read<'s, Vec<AssetWorkplaceData>>,
+        Read<'s, Vec<AssetItemData>>,
+        Read<'s, Vec<Box<dyn AIAction>>>,
+        Read<'s, MiscBuild>,
+        Read<'s, MiscSpatialGrid>,
+        WriteExpect<'s, MiscChunks>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, ComponentWorkplace>,
+        ReadStorage<'s, ComponentAgent>,
+        ReadStorage<'s, ComponentActionState>,
+        ReadStorage<'s, ComponentMovement>,
+        ReadStorage<'s, ComponentStockpile>,
+        ReadStorage<'s, ComponentPrice>,
+        ReadStorage<'s, ComponentPlayerControlled>,
+        WriteStorage<'s, ComponentDecisionLog>,
+        Read<'s, Vec<AIAxis>>,
+        Write<'s, MiscSelection>,
+        (ReadStorage<'s, ComponentMemory>, Read<'s, MiscTime>),
+    );
+
+    fn run(&mut self, (entities, events, input, active_camera, dimensions, workplace_datas, item_datas, action_datas, build, spatial, mut chunks, ui_finder, mut ui_texts, cameras, transforms, workplaces, agents, states, movements, stockpiles, prices, player, mut logs, axis_datas, mut selection, (memories, time)): Self::SystemData) {
+        let mut status = None;
+
+        for event in events.read(&mut self.event_reader) {
+            if let InputEvent::ActionPressed(action) = event {
+                if let Some(target) = selection.target.filter(|target| agents.contains(*target)) {
+                    if action == "trace" {
+                        if logs.contains(target) {
+                            logs.remove(target);
+                        } else {
+                            logs.insert(target, ComponentDecisionLog { decisions: VecDeque::new(), limit: 32 }).unwrap();
+                        }
+                    } else if action == "dump_trace" {
+                        if let Some(log) = logs.get(target) {
+                            let path = application_root_dir().unwrap().join(format!("decisions_{}.ron", target.id()));
+                            let decisions: Vec<&AIDecision> = log.decisions.iter().collect();
+
+                            let result = to_string_pretty(&decisions, PrettyConfig::default())
+                                .map_err(|e| e.to_string())
+                                .and_then(|out| write(&path, out).map_err(|e| e.to_string()));
+
+                            status = Some(match result {
+                                Ok(()) => format!("Dumped decisions to {}", path.display()),
+                                Err(e) => format!("Failed to dump decisions: {}", e),
+                            });
+                        }
+                    }
+                }
+            }
+
+            if let InputEvent::MouseButtonPressed(winit::MouseButton::Left) = event {
+                if build.active {
+                    continue;
+                }
+
+                selection.target = None;
+
+                if let Some(mouse_position) = input.mouse_position() {
+                    let mut camera_join = (&cameras, &transforms).join();
+
+                    if let Some((camera, camera_transform)) = active_camera
+                        .entity
+                        .and_then(|a| camera_join.get(a, &entities))
+                        .or_else(|| camera_join.next())
+                    {
+                        let coord = camera.projection()
+                            .screen_to_world_point(
+                                Point3::new(mouse_position.0, mouse_position.1, 0.0),
+                                Vector2::new(dimensions.width(), dimensions.height()),
+                                camera_transform,
+                            );
+                        let coord = Vector3::new(coord[0], coord[1], coord[2]);
+
+                        selection.target = spatial
+                            .get_nearest(&coord, 1, |e| agents.contains(e) && !player.contains(e))
+                            .into_iter()
+                            .find(|e| get_distance(&coord, transforms.get(*e).unwrap().translation()) <= 1.0)
+                            .or_else(|| chunks
+                                .to_tile(&coord)
+                                .and_then(|point| chunks.get(&point))
+                                .and_then(|tile| tile.building)
+                                .filter(|entity| workplaces.contains(*entity)));
+                    }
+                }
+            }
+        }
+
+        if selection.target.map_or(false, |target| !entities.is_alive(target)) {
+            selection.target = None;
+        }
+
+        let mut lines = Vec::new();
+
+        if let Some(target) = selection.target {
+            if let Some(workplace) = workplaces.get(target) {
+                let workplace_data = &workplace_datas[workplace.variant as usize];
+
+                lines.push(format!("Workplace {} ({})", target.id(), workplace_data.name));
+
+                if let Some(point) = chunks.to_tile(transforms.get(target).unwrap().translation()) {
+                    for y in point[1].saturating_sub(workplace_data.size.1)..=point[1] + workplace_data.size.1 {
+                        for x in point[0].saturating_sub(workplace_data.size.0)..=point[0] + workplace_data.size.0 {
+                            let point = Point3::new(x, y, point[2]);
+
+                            if chunks.get(&point).map_or(false, |tile| tile.building == Some(target)) {
+                                chunks.set_overlay(&point, OVERLAY_SELECTION);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let Some(agent) = agents.get(target) {
+                lines.push(format!("Agent {}", target.id()));
+
+                if let Some(point) = chunks.to_tile(transforms.get(target).unwrap().translation()) {
+                    chunks.set_overlay(&point, OVERLAY_SELECTION);
+                }
+
+                match action_datas.get(agent.current as usize) {
+                    Some(action_data) => {
+                        lines.push(format!("Action: {}", action_data.get_name()));
+                        lines.push(format!("Delay: {}", states.get(target).and_then(|state| state.delay).unwrap_or(0)));
+                    }
+                    None => lines.push("Action: None".to_string()),
+                }
+
+                lines.push(format!("Target: {}", agent.target.map_or("None".to_string(), |entity| entity.id().to_string())));
+
+                if !agent.plan.is_empty() {
+                    let plan: Vec<&str> = agent.plan.iter().filter_map(|step| action_datas.get(*step as usize)).map(|action_data| action_data.get_name().as_str()).collect();
+                    lines.push(format!("Plan: {}", plan.join(" > ")));
+                }
+
+                if let Some(memory) = memories.get(target) {
+                    let stamp = time.get_stamp();
+
+                    for (market, known) in memory.markets.iter() {
+                        lines.push(format!("Market {} seen {}s ago", market.id(), stamp.saturating_sub(known.time)));
+                    }
+                }
+            }
+
+            if let Some(decision) = logs.get(target).and_then(|log| log.decisions.back()) {
+                lines.push(format!("Decision at {}:", decision.time));
+
+                for score in decision.scores.iter() {
+                    let chosen = score.action == decision.action && score.target == decision.target;
+
+                    lines.push(format!(
+                        "{}{} -> {}: {:.3}",
+                        if chosen { "* " } else { "  " },
+                        action_datas.get(score.action as usize).map_or("?".to_string(), |action_data| action_data.get_name().clone()),
+                        score.target.map_or("None".to_string(), |target| target.to_string()),
+                        score.weight,
+                    ));
+
+                    for axis in score.axes.iter() {
+                        lines.push(format!(
+                            "    {}: {:.2} -> {:.2}",
+                            axis_datas.get(axis.axis as usize).map_or("?".to_string(), |axis_data| axis_data.name.clone()),
+                            axis.input,
+                            axis.output,
+                        ));
+                    }
+                }
+            }
+
+            if let Some(movement) = movements.get(target) {
+                let path: Vec<String> = movement.targets.iter().rev().map(|point| format!("({}, {})", point[0], point[1])).collect();
+
+                lines.push(format!("Path: {}", path.join(" ")));
+            }
+
+            if let Some(stockpile) = stockpiles.get(target) {
+                for i in 0..NUM_ITEM {
+                    let name = item_datas.get(i).map_or("?", |item_data| item_data.name.as_str());
+
+                    match prices.get(target) {
+                        Some(price) => lines.push(format!("{}: {} buy {} sell {}", name, stockpile.items[i], price.buy[i], price.sell[i])),
+                        None => lines.push(format!("{}: {}", name, stockpile.items[i])),
+                    }
+                }
+            }
+        }
+
+        if let Some(text) = ui_finder.find("Inspector").and_then(|entity| ui_texts.get_mut(entity)) {
+            text.text = lines.join("\n");
+        }
+
+        if let Some(status) = status {
+            if let Some(text) = ui_finder.find("Status").and_then(|entity| ui_texts.get_mut(entity)) {
+                text.text = status;
+            }
+        }
+    }
+}
+
+#[derive(SystemDesc)]
+#[system_desc(name(SystemTradeDesc))]
+pub struct SystemTrade {
+    #[system_desc(event_channel_reader)]
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+}
+impl SystemTrade {
+    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        SystemTrade { event_reader }
+    }
+}
+impl<'s> System<'s> for SystemTrade {
+    type SystemData = (
+        Entities<'s>,
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Read<'s, Vec<AssetWorkplaceData>>,
+        Read<'s, Vec<AssetItemData>>,
+        Read<'s, Vec<Box<dyn AIAction>>>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, ComponentWorkplace>,
+        ReadStorage<'s, ComponentPlayerControlled>,
+        WriteStorage<'s, ComponentStockpile>,
+        WriteStorage<'s, ComponentPrice>,
+        WriteStorage<'s, ComponentAgent>,
+        Write<'s, MiscSelection>,
+    );
+
+    fn run(&mut self, (entities, events, workplace_datas, item_datas, action_datas, ui_finder, mut ui_texts, transforms, workplaces, player, mut stockpiles, mut prices, mut agents, mut selection): Self::SystemData) {
+        let me = match (&entities, &player).join().next() {
+            Some((entity, _)) => entity,
+            None => return,
+        };
+
+        for event in events.read(&mut self.event_reader) {
+            match event {
+                InputEvent::ActionPressed(action) => {
+                    let target = match selection.target.filter(|target| workplaces.contains(*target)) {
+                        Some(target) => target,
+                        None => continue,
+                    };
+                    let workplace_data = match workplace_datas.get(workplaces.get(target).unwrap().variant as usize) {
+                        Some(workplace_data) => workplace_data,
+                        None => continue,
+                    };
+                    let reach = workplace_data.size.0.max(workplace_data.size.1) as f32 / 2.0 + 1.5;
+                    let near = get_distance(transforms.get(me).unwrap().translation(), transforms.get(target).unwrap().translation()) <= reach;
+                    let item = selection.item as usize;
+
+                    match action.as_str() {
+                        "trade_item" => {
+                            selection.item = (selection.item % (NUM_ITEM as u8 - 1)) + 1;
+                        }
+                        "trade_buy" if near && prices.contains(target) => {
+                            trade_buy(&me, &target, item, &mut stockpiles, &mut prices);
+                        }
+                        "trade_sell" if near && prices.contains(target) => {
+                            trade_sell(&me, &target, item, &mut stockpiles, &mut prices);
+                        }
+                        "work" if stockpiles.get(me).map_or(false, |stockpile| workplace_data.inputs.iter().enumerate().all(|(i, input)| stockpile.items[i] >= *input as u16)) => {
+                            let variant = workplaces.get(target).unwrap().variant;
+                            let work = action_datas.iter().position(|action_data| match action_data.get_data().effect {
+                                Some(AIEffect::Work(work)) => work == variant,
+                                _ => false,
+                            });
+
+                            if let Some(index) = work {
+                                if let Some(agent) = agents.get_mut(me) {
+                                    if agent.current != 255 {
+                                        agent.aborted = Some(agent.current);
+                                    }
+
+                                    agent.current = index as u8;
+                                    agent.target = Some(target);
+                                    agent.fresh = true;
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(text) = ui_finder.find("Panel").and_then(|entity| ui_texts.get_mut(entity)) {
+            text.text = match selection.target.and_then(|target| workplaces.get(target).and_then(|workplace| workplace_datas.get(workplace.variant as usize)).map(|workplace_data| (target, workplace_data))) {
+                Some((target, workplace_data)) => {
+                    let item = selection.item as usize;
+                    let stock = stockpiles.get(target).map_or(0, |stockpile| stockpile.items[item]);
+                    let quote = prices.get(target).map_or("no trade".to_string(), |price| format!("buy {} sell {}", price.sell[item], price.buy[item]));
+                    let own = stockpiles.get(me).map_or([0; NUM_ITEM], |stockpile| stockpile.items);
+
+                    format!(
+                        "{} | {}: {} stock {} | money {} held {} | C item Z buy X sell V work",
+                        workplace_data.name,
+                        item_datas.get(item).map_or("?", |item_data| item_data.name.as_str()),
+                        quote,
+                        stock,
+                        own[0],
+                        own[item],
+                    )
+                }
+                None => "".to_string(),
+            };
+        }
+    }
+}
+
+#[derive(SystemDesc)]
+#[system_desc(name(SystemCurveEditorDesc))]
+pub struct SystemCurveEditor {
+    #[system_desc(event_channel_reader)]
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+}
+impl SystemCurveEditor {
+    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        SystemCurveEditor { event_reader }
+    }
+}
+impl<'s> System<'s> for SystemCurveEditor {
+    type SystemData = (
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Write<'s, Vec<AIAxis>>,
+        Read<'s, AssetDefPaths>,
+        WriteExpect<'s, MiscCurveEditor>,
+        WriteStorage<'s, UiTransform>,
+        WriteStorage<'s, UiText>,
+        WriteStorage<'s, HiddenPropagate>,
+    );
+
+    fn run(&mut self, (events, mut axis_datas, paths, mut editor, mut ui_transforms, mut ui_texts, mut hiddens): Self::SystemData) {
+        for event in events.read(&mut self.event_reader) {
+            if let InputEvent::ActionPressed(action) = event {
+                if action == "curve_editor" {
+                    editor.active = !editor.active;
+
+                    if editor.active {
+                        hiddens.remove(editor.panel);
+                    } else {
+                        hiddens.insert(editor.panel, HiddenPropagate::default()).unwrap();
+                    }
+                }
+
+                if !editor.active || axis_datas.is_empty() {
+                    continue;
+                }
+
+                let (_, step) = AXIS_PARAMS[editor.param];
+
+                match action.as_str() {
+                    "curve_axis_next" => editor.axis = (editor.axis + 1) % axis_datas.len(),
+                    "curve_axis_prev" => editor.axis = (editor.axis + axis_datas.len() - 1) % axis_datas.len(),
+                    "curve_param" => editor.param = (editor.param + 1) % AXIS_PARAMS.len(),
+                    "curve_up" | "curve_down" => {
+                        let axis = &mut axis_datas[editor.axis];
+                        let value = axis.get_param(editor.param) + if action == "curve_up" { step } else { -step };
+
+                        if axis.set_param(editor.param, value) {
+                            let i = editor.axis;
+                            editor.modified.insert(i);
+                        } else {
+                            editor.status = format!("{} {:.2} gives an undefined curve", AXIS_PARAMS[editor.param].0, value);
+                        }
+                    }
+                    "curve_save" => {
+                        editor.status = match paths.axis.get(editor.axis) {
+                            Some(path) => match to_string_pretty(&axis_datas[editor.axis], PrettyConfig::default()) {
+                                Ok(out) => match write(path, format!("#![enable(implicit_some)]\n{}", out)) {
+                                    Ok(_) => {
+                                        let i = editor.axis;
+                                        editor.modified.remove(&i);
+                                        format!("Saved {}", path.display())
+                                    }
+                                    Err(e) => format!("Failed to save axis: {}", e),
+                                },
+                                Err(e) => format!("Failed to save axis: {}", e),
+                            },
+                            None => "No file for axis".to_string(),
+                        };
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if !editor.active {
+            return;
+        }
+
+        let axis = match axis_datas.get(editor.axis) {
+            Some(axis) => axis,
+            None => return,
+        };
+
+        for (i, dot) in editor.dots.iter().enumerate() {
+            let x = i as f32 / (editor.dots.len() - 1).max(1) as f32;
+            let y = axis.get_output(x);
+
+            if let Some(transform) = ui_transforms.get_mut(*dot) {
+                transform.local_x = 10. + x * CURVE_WIDTH;
+                transform.local_y = 10. + y * CURVE_HEIGHT;
+            }
+        }
+
+        if let Some(text) = ui_texts.get_mut(editor.text) {
+            let params: Vec<String> = AXIS_PARAMS.iter().enumerate().map(|(i, (name, _))| {
+                let value = axis.get_param(i);
+
+                if i == editor.param {
+                    return format!("[{}: {:.2}]", name, value);
+                } else {
+                    return format!("{}: {:.2}", name, value);
+                }
+            }).collect();
+
+            text.text = format!("{} {}/{} ({:?})\n{}\n{}", axis.name, editor.axis, axis_datas.len() - 1, axis.curve, params.join(" "), editor.status);
+        }
+    }
+}
+
+#[derive(SystemDesc)]
+#[system_desc(name(SystemMapExportDesc))]
+pub struct SystemMapExport {
+    #[system_desc(event_channel_reader)]
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+}
+impl SystemMapExport {
+    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        SystemMapExport { event_reader }
+    }
+}
+impl<'s> System<'s> for SystemMapExport {
+    type SystemData = (
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Read<'s, MapGenConfig>,
+        Option<Read<'s, MapGen>>,
+        ReadExpect<'s, MiscChunks>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+    );
+
+    fn run(&mut self, (events, config, map_gen, chunks, ui_finder, mut ui_texts): Self::SystemData) {
+        for event in events.read(&mut self.event_reader) {
+            if let InputEvent::ActionPressed(action) = event {
+                if action == "export_map" {
+                    let path = application_root_dir().unwrap().join(&config.export);
+                    let dir = get_chunk_dir(&application_root_dir().unwrap(), &config);
+
+                    let status = match get_grid(&chunks, map_gen.as_ref().map(|map_gen| &**map_gen), &dir).and_then(|grid| save_grid(&grid, &path)) {
+                        Ok(()) => format!("Exported map to {}", path.display()),
+                        Err(e) => format!("Failed to export map: {}", e),
+                    };
+
+                    if let Some(text) = ui_finder.find("Status").and_then(|entity| ui_texts.get_mut(entity)) {
+                        text.text = status;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SystemReload {
+    tick: u32,
+    modified: HashMap<PathBuf, std::time::SystemTime>,
+}
+impl SystemReload {
+    fn changed(&mut self, paths: &[PathBuf]) -> bool {
+        let mut changed = false;
+
+        for path in paths.iter() {
+            match metadata(path).and_then(|meta| meta.modified()) {
+                Ok(modified) => {
+                    if let Some(old) = self.modified.insert(path.clone(), modified) {
+                        changed |= old != modified;
+                    }
+                }
+                Err(_) => changed |= self.modified.remove(path).is_some(),
+            }
+        }
+
+        return changed;
+    }
+}
+impl<'s> System<'s> for SystemReload {
+    type SystemData = (
+        Read<'s, AssetDefPaths>,
+        Write<'s, Vec<AIAxis>>,
+        Write<'s, Vec<AssetWorkplaceData>>,
+        Write<'s, Vec<AssetItemData>>,
+        Write<'s, Vec<AIArchetypeData>>,
+        Write<'s, Vec<Box<dyn AIAction>>>,
+        Write<'s, MiscReload>,
+        Option<Write<'s, MiscCurveEditor>>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+    );
+
+    fn run(&mut self, (paths, mut axis_datas, mut workplace_datas, mut item_datas, mut archetype_datas, mut action_datas, mut reload, editor, ui_finder, mut ui_texts): Self::SystemData) {
+        self.tick += 1;
+        if self.tick % 60 != 1 {
+            return;
+        }
+
+        // Actions are checked against the axis and workplace counts, so they are reloaded
+        // whenever either of those changes as well.
+        let mut reload_actions = self.changed(&paths.action);
+
+        if self.changed(&paths.axis) {
+            match load_axes(&paths.axis) {
+                Ok(mut axis) => {
+                    reload.errors[0].clear();
+                    reload.notices.clear();
+
+                    for i in editor.as_ref().map_or(Vec::new(), |editor| editor.modified.iter().cloned().collect()) {
+                        if let (Some(edited), Some(loaded)) = (axis_datas.get(i), axis.get_mut(i)) {
+                            reload.notices.push(format!("{}: kept unsaved curve editor changes", edited.name));
+                            *loaded = edited.clone();
+                        }
+                    }
+
+                    *axis_datas = axis;
+                    reload_actions = true;
+                }
+                Err(errors) => reload.errors[0] = errors,
+            }
+        }
+
+        if self.changed(&paths.workplace) {
+            match load_workplaces(&paths.workplace) {
+                Ok(workplaces) => {
+                    *workplace_datas = workplaces;
+                    reload.errors[1].clear();
+                    reload_actions = true;
+                }
+                Err(errors) => reload.errors[1] = errors,
+            }
+        }
+
+        if self.changed(&paths.item) {
+            match load_items(&paths.item) {
+                Ok(items) => {
+                    *item_datas = items;
+                    reload.errors[2].clear();
+                }
+                Err(errors) => reload.errors[2] = errors,
+            }
+        }
+
+        if self.changed(&paths.archetype) {
+            match load_archetypes(&paths.archetype) {
+                Ok(archetypes) => {
+                    *archetype_datas = archetypes;
+                    reload.errors[3].clear();
+                }
+                Err(errors) => reload.errors[3] = errors,
+            }
+        }
+
+        if reload_actions {
+            match load_actions(&paths.action, axis_datas.len(), workplace_datas.len()) {
+                Ok(actions) => {
+                    *action_datas = get_actions(actions);
+                    reload.errors[4].clear();
+                }
+                Err(errors) => {
+                    if action_datas.iter().any(|action| !check_action(action.get_data(), axis_datas.len(), workplace_datas.len()).is_empty()) {
+                        action_datas.clear();
+                    }
+
+                    reload.errors[4] = errors;
+                }
+            }
+        }
+
+        if let Some(text) = ui_finder.find("Reload").and_then(|entity| ui_texts.get_mut(entity)) {
+            let errors: Vec<&str> = reload.errors.iter().flatten().map(|e| e.as_str()).collect();
+            let mut lines = Vec::new();
+
+            if !errors.is_empty() {
+                lines.push(format!("Reload failed:\n{}", errors.join("\n")));
+            }
+
+            if !reload.notices.is_empty() {
+                lines.push(format!("Reload notes:\n{}", reload.notices.join("\n")));
+            }
+
+            text.text = lines.join("\n");
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct SystemMapMode;
 impl<'s> System<'s> for SystemMapMode {
@@ -396,7 +1138,7 @@ impl<'s> System<'s> for SystemPrice {
     fn run(&mut self, (stockpiles, mut prices): Self::SystemData) {
         (&stockpiles, &mut prices).par_join().for_each(|(stockpile, price)| {
             for i in 1..NUM_ITEM {
-                if price.update[i] {
+                if price.update[i] && stockpile.items[i] > 0 {
                     price.buy[i] = get_price(true, 1, (stockpile.items[0], price.weight[0], price.decay[0]), (stockpile.items[i], price.weight[i], price.decay[i]));
                     price.sell[i] = get_price(false, 1, (stockpile.items[0], price.weight[0], price.decay[0]), (stockpile.items[i], price.weight[i], price.decay[i]));
                 }
@@ -406,76 +1148,370 @@ impl<'s> System<'s> for SystemPrice {
 }
 
 #[derive(Default)]
-pub struct SystemAI;
+pub struct SystemSpatial;
+impl<'s> System<'s> for SystemSpatial {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Transform>,
+        Write<'s, MiscSpatialGrid>,
+    );
+
+    fn run(&mut self, (entities, transforms, mut spatial): Self::SystemData) {
+        spatial.clear();
+
+        for (entity, transform) in (&entities, &transforms).join() {
+            spatial.insert(entity, transform.translation());
+        }
+    }
+}
+
+fn get_buildings<'a>(entities: &Entities, workplaces: &ReadStorage<ComponentWorkplace>, workplace_datas: &'a [AssetWorkplaceData]) -> Vec<(Entity, Point3<u32>, &'a AssetWorkplaceData)> {
+    return (entities, workplaces).join()
+        .filter_map(|(entity, workplace)| {
+            let workplace_data = workplace_datas.get(workplace.variant as usize)?;
+
+            return Some((entity, get_origin(&workplace.entrance?, workplace_data), workplace_data));
+        })
+        .collect();
+}
+
+#[derive(Default)]
+pub struct SystemMemory {
+    tick: u32,
+}
+impl<'s> System<'s> for SystemMemory {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, ComponentWorkplace>,
+        ReadStorage<'s, ComponentStockpile>,
+        ReadStorage<'s, ComponentPrice>,
+        WriteStorage<'s, ComponentMemory>,
+        Read<'s, MiscSpatialGrid>,
+        Read<'s, MiscTime>,
+    );
+
+    fn run(&mut self, (entities, transforms, workplaces, stockpiles, prices, mut memories, spatial, time): Self::SystemData) {
+        let stamp = time.get_stamp();
+
+        for (transform, memory) in (&transforms, &mut memories).join() {
+            memory.markets.retain(|market, _| entities.is_alive(*market));
+
+            observe(&mut memory.markets, transform.translation(), &spatial, |market| {
+                if !workplaces.get(market).map_or(false, |w| w.variant == 3) {
+                    return None;
+                }
+
+                let (price, stockpile) = (prices.get(market)?, stockpiles.get(market)?);
+
+                return Some(AIMemory { buy: price.buy, sell: price.sell, stock: stockpile.items, time: stamp });
+            });
+        }
+
+        self.tick += 1;
+
+        if self.tick % MEMORY_GOSSIP_TICKS != 0 {
+            return;
+        }
+
+        let heard: Vec<(Entity, Vec<(Entity, AIMemory)>)> = (&entities, &transforms, &memories).par_join().map(|(entity, transform, _)| {
+            let mut heard = Vec::new();
+
+            for other in spatial.get_within(transform.translation(), MEMORY_GOSSIP, |e| e != entity && memories.contains(e)) {
+                for (market, memory) in memories.get(other).unwrap().markets.iter() {
+                    heard.push((*market, *memory));
+                }
+            }
+
+            return (entity, heard);
+        }).collect();
+
+        for (entity, heard) in heard.into_iter() {
+            let memory = memories.get_mut(entity).unwrap();
+
+            for (market, gossip) in heard.into_iter() {
+                remember(&mut memory.markets, market, gossip);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SystemChunk;
+impl<'s> System<'s> for SystemChunk {
+    type SystemData = (
+        Entities<'s>,
+        Read<'s, ActiveCamera>,
+        ReadExpect<'s, ScreenDimensions>,
+        Read<'s, MapGenConfig>,
+        Option<Read<'s, MapGen>>,
+        Read<'s, Vec<AssetWorkplaceData>>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, ComponentMovement>,
+        ReadStorage<'s, ComponentWorkplace>,
+        WriteStorage<'s, Transform>,
+        WriteStorage<'s, TileMap<MiscTileLayer>>,
+        WriteExpect<'s, MiscChunks>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+    );
+
+    fn run(&mut self, (entities, active_camera, dimensions, config, map_gen, workplace_datas, cameras, movements, workplaces, mut transforms, mut tilemaps, mut chunks, ui_finder, mut ui_texts): Self::SystemData) {
+        let (columns, rows) = chunks.dimensions();
+        let dir = get_chunk_dir(&application_root_dir().unwrap(), &config);
+        let keep = config.keep as i64;
+        let mut status = None;
+
+        let mut visible = HashSet::new();
+        let mut needed = HashSet::new();
+        let mut kept = HashSet::new();
+
+        let add = |set: &mut HashSet<(u32, u32)>, (x0, y0): (i64, i64), (x1, y1): (i64, i64)| {
+            for y in y0.max(0)..=y1.min(rows as i64 - 1) {
+                for x in x0.max(0)..=x1.min(columns as i64 - 1) {
+                    set.insert((x as u32, y as u32));
+                }
+            }
+        };
+
+        {
+            let mut camera_join = (&cameras, &transforms).join();
+
+            if let Some((camera, camera_transform)) = active_camera
+                .entity
+                .and_then(|a| camera_join.get(a, &entities))
+                .or_else(|| camera_join.next())
+            {
+                let corners: Vec<(i64, i64)> = [(0.0, 0.0), (dimensions.width(), dimensions.height())]
+                    .iter()
+                    .map(|(x, y)| {
+                        let coord = camera.projection()
+                            .screen_to_world_point(
+                                Point3::new(*x, *y, 0.0),
+                                Vector2::new(dimensions.width(), dimensions.height()),
+                                camera_transform,
+                            );
+
+                        chunks.get_chunk_at(&Vector3::new(coord[0], coord[1], coord[2]))
+                    })
+                    .collect();
+                let min = (corners[0].0.min(corners[1].0), corners[0].1.min(corners[1].1));
+                let max = (corners[0].0.max(corners[1].0), corners[0].1.max(corners[1].1));
+
+                add(&mut visible, min, max);
+                add(&mut kept, (min.0 - 1, min.1 - 1), (max.0 + 1, max.1 + 1));
+            }
+        }
+
+        needed.extend(visible.iter().cloned());
+
+        for (transform, _) in (&transforms, &movements).join() {
+            let (x, y) = chunks.get_chunk_at(transform.translation());
+
+            add(&mut needed, (x - keep, y - keep), (x + keep, y + keep));
+            add(&mut kept, (x - keep - 1, y - keep - 1), (x + keep + 1, y + keep + 1));
+        }
+
+        for (transform, _) in (&transforms, &workplaces).join() {
+            let (x, y) = chunks.get_chunk_at(transform.translation());
+
+            add(&mut needed, (x - keep, y - keep), (x + keep, y + keep));
+            add(&mut kept, (x - keep - 1, y - keep - 1), (x + keep + 1, y + keep + 1));
+        }
+
+        let buildings = get_buildings(&entities, &workplaces, &workplace_datas);
+
+        for chunk in needed.iter() {
+            if !chunks.chunks.contains_key(chunk) {
+                let tiles = match load_chunk(*chunk, &dir) {
+                    Ok(Some(tiles)) => Some(tiles),
+                    Ok(None) => map_gen.as_ref().map(|map_gen| map_gen.gen_chunk(*chunk, chunks.size)),
+                    Err(e) => {
+                        status = Some(format!("Failed to load chunk {},{}: {}", chunk.0, chunk.1, e));
+                        None
+                    }
+                };
+
+                if let Some(mut tiles) = tiles {
+                    set_buildings(*chunk, &mut tiles, &chunks, &buildings);
+                    chunks.chunks.insert(*chunk, tiles);
+                }
+            }
+        }
+
+        let far: Vec<(u32, u32)> = chunks.chunks.keys().filter(|chunk| !kept.contains(*chunk)).cloned().collect();
+
+        for chunk in far {
+            let tiles = chunks.chunks.remove(&chunk).unwrap();
+
+            if let Err(e) = save_chunk(&tiles, chunk, &dir) {
+                status = Some(format!("Failed to save chunk {},{}: {}", chunk.0, chunk.1, e));
+            }
+
+            let size = chunks.size;
+            chunks.dirty.retain(|point| (point[0] / size, point[1] / size) != chunk);
+        }
+
+        let hidden: Vec<(u32, u32)> = chunks.entities.keys().filter(|chunk| !visible.contains(*chunk) || !chunks.chunks.contains_key(*chunk)).cloned().collect();
+
+        for chunk in hidden {
+            entities.delete(chunks.entities.remove(&chunk).unwrap()).unwrap();
+        }
+
+        for chunk in visible.iter() {
+            if !chunks.entities.contains_key(chunk) {
+                if let Some(map) = chunks.get_map(*chunk) {
+                    let entity = entities
+                        .build_entity()
+                        .with(map, &mut tilemaps)
+                        .with(Transform::from(chunks.get_offset(*chunk) + Vector3::new(0.0, 0.0, chunks.depth)), &mut transforms)
+                        .build();
+
+                    chunks.entities.insert(*chunk, entity);
+                }
+            }
+        }
+
+        let dirty: Vec<Point3<u32>> = chunks.dirty.drain().collect();
+
+        for point in dirty.iter() {
+            if let Some(map) = chunks.entities.get(&chunks.get_chunk(point)).and_then(|entity| tilemaps.get_mut(*entity)) {
+                chunks.set_point(point, map);
+            }
+        }
+
+        if let Some(status) = status {
+            if let Some(text) = ui_finder.find("Status").and_then(|entity| ui_texts.get_mut(entity)) {
+                text.text = status;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SystemTravel;
+impl<'s> System<'s> for SystemTravel {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, ComponentWorkplace>,
+        ReadExpect<'s, MiscChunks>,
+        Read<'s, MapGenConfig>,
+        Option<Read<'s, MapGen>>,
+        Read<'s, Vec<AssetWorkplaceData>>,
+        Write<'s, MiscTravel>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+    );
+
+    fn run(&mut self, (entities, transforms, workplaces, chunks, config, map_gen, workplace_datas, mut travel, ui_finder, mut ui_texts): Self::SystemData) {
+        let dir = get_chunk_dir(&application_root_dir().unwrap(), &config);
+        let mut status = None;
+        let buildings = get_buildings(&entities, &workplaces, &workplace_datas);
+
+        travel.invalidate();
+        travel.fields.retain(|entity, _| workplaces.contains(*entity));
+
+        for (entity, workplace, transform) in (&entities, &workplaces, &transforms).join() {
+            if let Some(goal) = workplace.entrance.or_else(|| chunks.to_tile(transform.translation())) {
+                if travel.fields.get(&entity).map_or(true, |field| field.goal != goal) {
+                    let field = get_travel_field(&goal, &chunks, &mut travel.costs, |chunk| {
+                        let tiles = match load_chunk(chunk, &dir) {
+                            Ok(Some(tiles)) => Some(tiles),
+                            Ok(None) => map_gen.as_ref().map(|map_gen| map_gen.gen_chunk(chunk, chunks.size)),
+                            Err(e) => {
+                                status = Some(format!("Failed to load chunk {},{}: {}", chunk.0, chunk.1, e));
+                                None
+                            }
+                        };
+
+                        return tiles.map(|mut tiles| {
+                            set_buildings(chunk, &mut tiles, &chunks, &buildings);
+                            tiles
+                        });
+                    });
+
+                    travel.fields.insert(entity, field);
+                }
+            }
+        }
+
+        if let Some(status) = status {
+            if let Some(text) = ui_finder.find("Status").and_then(|entity| ui_texts.get_mut(entity)) {
+                text.text = status;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SystemAI {
+    tick: u64,
+}
 impl<'s> System<'s> for SystemAI {
     type SystemData = (
         Entities<'s>,
         Read<'s, Vec<AssetWorkplaceData>>,
         Read<'s, Vec<AssetItemData>>,
         Read<'s, Vec<AIAxis>>,
-        Write<'s, Vec<Box<dyn AIAction>>>,
+        Read<'s, Vec<Box<dyn AIAction>>>,
         WriteStorage<'s, ComponentAgent>,
-        WriteStorage<'s, TileMap<MiscTile>>,
+        WriteStorage<'s, ComponentActionState>,
+        ReadExpect<'s, MiscChunks>,
         WriteStorage<'s, Transform>,
         WriteStorage<'s, ComponentWorkplace>,
         WriteStorage<'s, ComponentStockpile>,
         WriteStorage<'s, ComponentMovement>,
         WriteStorage<'s, ComponentPrice>,
+        Read<'s, MiscSpatialGrid>,
+        Read<'s, MiscTravel>,
+        Read<'s, MiscTime>,
+        WriteStorage<'s, ComponentDecisionLog>,
+        Read<'s, Vec<AIArchetypeData>>,
+        ReadStorage<'s, ComponentMemory>,
     );
 
-    fn run(&mut self, (entities, workplace_datas, item_datas, axis_datas, mut action_datas, mut agents, mut tilemaps, mut transforms, mut workplaces, mut stockpiles, mut movements, mut prices): Self::SystemData ) {
-        let mut ai_data = (&entities, workplace_datas, item_datas, axis_datas, tilemaps, transforms, workplaces, stockpiles, movements, prices);
-
-        (&entities, &mut agents).par_join().for_each(|(entity, agent)| {
-            if agent.current == 255 {
-                let mut evals: Vec<(u8, Option<Entity>, f32)> = Vec::new();
-
-                for action in agent.actions.iter() {
-                    if *action == 255 {
-                        continue;
-                    }
-
-                    if let Some(eval) = action_datas[*action as usize].eval(&entity, &ai_data) {
-                        evals.push(eval);
-                    }
-                }
+    fn run(&mut self, (entities, workplace_datas, item_datas, axis_datas, action_datas, mut agents, mut states, chunks, mut transforms, mut workplaces, mut stockpiles, mut movements, mut prices, spatial, travel, time, mut logs, archetype_datas, memories): Self::SystemData ) {
+        if action_datas.is_empty() {
+            return;
+        }
 
-                let current: (u8, Option<Entity>, f32);
+        let mut ai_data = (&entities, workplace_datas, item_datas, axis_datas, chunks, transforms, workplaces, stockpiles, movements, prices, spatial, travel, memories);
+        let tick = self.tick;
 
-                if evals.len() > 1 {
-                    let dist = WeightedIndex::new(evals.iter().map(|eval| eval.2.powf(5.0))).unwrap();
+        self.tick += 1;
 
-                    current = evals[dist.sample(&mut thread_rng())];
-                } else {
-                    current = evals[0];
-                }
+        let mut commands: Vec<(Entity, Vec<AICommand>, bool)> = (&entities, &mut agents, &mut states, (&mut logs).maybe()).par_join().map(|(entity, agent, state, log)| {
+            let mut commands = Vec::new();
+            let mut finished = false;
+            let archetype = archetype_datas.get(agent.archetype as usize);
 
-                agent.current = current.0;
+            decide(&entity, agent, log, archetype, &action_datas, &ai_data, &time, &mut get_rng(&entity, tick));
 
-                if let Some(t) = current.1 {
-                    agent.target = Some(t.clone());
-                } else {
-                    agent.target = None;
-                }
+            let target = agent.target.unwrap_or(entity);
 
-                agent.fresh = true;
+            if let Some(aborted) = agent.aborted.take() {
+                action_datas[aborted as usize].abort(&entity, state, &ai_data, &mut commands);
             }
-        });
 
-        for (entity, agent) in (&entities, &mut agents).join() {
             if agent.fresh {
                 agent.fresh = false;
-                
-                if !action_datas[agent.current as usize].init(&entity, &agent.target.unwrap_or(entity.clone()), &mut ai_data) {
-                    agent.current = 255;
-                }
-            }
+                *state = ComponentActionState::default();
 
-            if agent.current != 255 {
-                if action_datas[agent.current as usize].run(&entity, &agent.target.unwrap_or(entity.clone()), &mut ai_data) {
+                if !action_datas[agent.current as usize].init(&entity, &target, state, &ai_data, &mut commands) {
                     agent.current = 255;
                 }
+            } else if agent.current != 255 {
+                finished = action_datas[agent.current as usize].run(&entity, &target, state, &ai_data, &mut commands);
             }
+
+            return (entity, commands, finished);
+        }).collect();
+
+        commands.sort_by_key(|(entity, _, _)| entity.id());
+
+        for (entity, commands, finished) in commands.into_iter() {
+            resolve(agents.get_mut(entity).unwrap(), finished, commands, &mut ai_data);
         }
     }
 }
@@ -486,47 +1522,45 @@ impl<'s> System<'s> for SystemMovement {
     type SystemData = (
         WriteStorage<'s, ComponentMovement>,
         WriteStorage<'s, Transform>,
-        WriteStorage<'s, TileMap<MiscTile>>,
+        ReadExpect<'s, MiscChunks>,
     );
 
-    fn run(&mut self, (mut movements, mut transforms, mut tilemaps): Self::SystemData) {
+    fn run(&mut self, (mut movements, mut transforms, chunks): Self::SystemData) {
         for (movement, mut transform) in (&mut movements, &mut transforms.restrict_mut()).join() {
             if movement.targets.len() > 0 {
                 let transform = transform.get_mut_unchecked();
 
-                for tilemap in (&mut tilemaps).join() {
-                    if let Ok(_) = tilemap.to_tile(transform.translation(), None) {
-                        let mut velocity = tilemap.to_world(movement.targets.last().unwrap(), None) - transform.translation();
-                        let distance = (velocity[0].powf(2.0) + velocity[1].powf(2.0) + velocity[2].powf(2.0)).sqrt();
+                if chunks.to_tile(transform.translation()).is_some() {
+                    let mut velocity = chunks.to_world(movement.targets.last().unwrap()) - transform.translation();
+                    let distance = (velocity[0].powf(2.0) + velocity[1].powf(2.0) + velocity[2].powf(2.0)).sqrt();
 
-                        if distance > movement.acceleration {
-                            velocity *= movement.acceleration / distance;
-                        }
+                    if distance > movement.acceleration {
+                        velocity *= movement.acceleration / distance;
+                    }
 
-                        movement.velocity += velocity;
-                        let speed = (movement.velocity[0].powf(2.0) + movement.velocity[1].powf(2.0) + movement.velocity[2].powf(2.0)).sqrt();
-                        let mut speed_limit = movement.speed_limit;
+                    movement.velocity += velocity;
+                    let speed = (movement.velocity[0].powf(2.0) + movement.velocity[1].powf(2.0) + movement.velocity[2].powf(2.0)).sqrt();
+                    let mut speed_limit = movement.speed_limit;
 
-                        if distance < 2.0 && movement.targets.len() <= 1 {
-                            speed_limit *= distance / 2.0;
-                        }
+                    if distance < 2.0 && movement.targets.len() <= 1 {
+                        speed_limit *= distance / 2.0;
+                    }
 
-                        if speed > speed_limit {
-                            movement.velocity *= speed_limit / speed;
-                        }
+                    if speed > speed_limit {
+                        movement.velocity *= speed_limit / speed;
+                    }
 
-                        *transform.translation_mut() += movement.velocity;
+                    *transform.translation_mut() += movement.velocity;
 
-                        if (movement.targets.len() > 1 && distance < 0.2)
-                        || (movement.targets.len() <= 1 && distance < 0.002) {
-                            movement.targets.pop();
-                        }
+                    if (movement.targets.len() > 1 && distance < 0.2)
+                    || (movement.targets.len() <= 1 && distance < 0.002) {
+                        movement.targets.pop();
+                    }
 
-                        if movement.targets.len() == 0 {
-                            movement.velocity[0] = 0.0;
-                            movement.velocity[1] = 0.0;
-                            movement.velocity[2] = 0.0;
-                        }
+                    if movement.targets.len() == 0 {
+                        movement.velocity[0] = 0.0;
+                        movement.velocity[1] = 0.0;
+                        movement.velocity[2] = 0.0;
                     }
                 }
             }
@@ -0,0 +1,80 @@
+use crate::misc::*;
+use crate::asset::*;
+use crate::ai::*;
+use crate::NUM_ITEM;
+
+use std::path::{ Path, PathBuf };
+
+pub fn load_workplaces(paths: &[PathBuf]) -> Result<Vec<AssetWorkplaceData>, Vec<String>> {
+    return load_defs::<AssetWorkplaceData, _>(paths, |workplace| {
+        let mut errors = check_workplace(workplace, NUM_ITEM);
+
+        for terrain in workplace.terrain.iter().filter(|terrain| !TERRAINS.contains(*terrain)) {
+            errors.push(format!("{}: terrain {} is not a terrain class", workplace.name, terrain));
+        }
+
+        return errors;
+    });
+}
+
+pub fn load_items(paths: &[PathBuf]) -> Result<Vec<AssetItemData>, Vec<String>> {
+    let items = load_defs::<AssetItemData, _>(paths, |_| Vec::new())?;
+
+    if items.len() != NUM_ITEM {
+        return Err(vec![format!("{} items defined, expected {}", items.len(), NUM_ITEM)]);
+    }
+
+    return Ok(items);
+}
+
+pub fn load_axes(paths: &[PathBuf]) -> Result<Vec<AIAxis>, Vec<String>> {
+    return load_defs::<AIAxis, _>(paths, |axis| {
+        let mut errors = check_axis(axis);
+        if errors.is_empty() {
+            errors.extend(check_curve(axis));
+        }
+
+        return errors;
+    });
+}
+
+pub fn load_actions(paths: &[PathBuf], axis_count: usize, workplace_count: usize) -> Result<Vec<AIActionData>, Vec<String>> {
+    let actions = load_defs::<AIActionData, _>(paths, |action| check_action(action, axis_count, workplace_count))?;
+
+    if actions.len() != NUM_ACTION {
+        return Err(vec![format!("{} actions defined, expected {}", actions.len(), NUM_ACTION)]);
+    }
+
+    return Ok(actions);
+}
+
+pub fn load_archetypes(paths: &[PathBuf]) -> Result<Vec<AIArchetypeData>, Vec<String>> {
+    return load_defs::<AIArchetypeData, _>(paths, check_archetype);
+}
+
+pub fn validate(root: &Path) -> Vec<String> {
+    let paths = get_def_paths(root);
+    let mut errors = Vec::new();
+
+    if let Err(e) = load_workplaces(&paths.workplace) {
+        errors.extend(e);
+    }
+
+    if let Err(e) = load_items(&paths.item) {
+        errors.extend(e);
+    }
+
+    if let Err(e) = load_axes(&paths.axis) {
+        errors.extend(e);
+    }
+
+    if let Err(e) = load_actions(&paths.action, paths.axis.len(), paths.workplace.len()) {
+        errors.extend(e);
+    }
+
+    if let Err(e) = load_archetypes(&paths.archetype) {
+        errors.extend(e);
+    }
+
+    return errors;
+}